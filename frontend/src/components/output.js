@@ -3,6 +3,40 @@ import Col from "react-bootstrap/Col";
 import { ConsoleType } from "../lib/Api";
 import './output.css';
 
+const BASE_COLORS = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+/// css color of a span color, palette index or rgb
+function color (value) {
+    if (value === null || value === undefined) {
+        return undefined;
+    }
+    if (value.Rgb !== undefined) {
+        return "rgb(" + value.Rgb.join(",") + ")";
+    }
+    const idx = value.Palette;
+    if (idx < 16) {
+        return BASE_COLORS[idx];
+    } else if (idx < 232) {
+        const steps = [0, 95, 135, 175, 215, 255];
+        const i = idx - 16;
+        return "rgb(" + steps[Math.floor(i / 36)] + "," + steps[Math.floor(i / 6) % 6] + "," + steps[i % 6] + ")";
+    } else {
+        const v = 8 + (idx - 232) * 10;
+        return "rgb(" + v + "," + v + "," + v + ")";
+    }
+}
+
+function spans (spans) {
+    return spans.map((span) => (<span style={{
+        color: color(span.fg),
+        backgroundColor: color(span.bg),
+        fontWeight: span.bold ? "bold" : undefined
+    }}>{span.text}</span>));
+}
+
 function lines (lines) {
     return lines.map((line) => parseLine(line));
 }
@@ -16,6 +50,14 @@ function parseLine (line) {
         return (<Col className="text-danger console-col">
             {line[ConsoleType.Stderr]}
         </Col>);
+    } else if (line[ConsoleType.StderrStyled] !== undefined) {
+        return (<Col className="text-danger console-col">
+            {spans(line[ConsoleType.StderrStyled])}
+        </Col>);
+    } else if (line[ConsoleType.StdoutStyled] !== undefined) {
+        return (<Col className="text-normal console-col">
+            {spans(line[ConsoleType.StdoutStyled])}
+        </Col>);
     } else if (line[ConsoleType.Stdin] !== undefined) {
         return (<Col className="text-primary console-col">
             {line[ConsoleType.Stdin]}
@@ -14,6 +14,8 @@ export const ServiceState = {
     Crashed: "Crashed",
     Stopping: "Stopping",
     Killed: "Killed",
+    Paused: "Paused",
+    TimedOut: "TimedOut",
 };
 
 export const ConsoleType = {
@@ -21,6 +23,8 @@ export const ConsoleType = {
     Stderr: "Stderr",
     Stdout: "Stdout",
     Stdin: "Stdin",
+    StdoutStyled: "StdoutStyled",
+    StderrStyled: "StderrStyled",
 };
 
 export function api_users () {
@@ -37,10 +41,21 @@ export function api_input (sid, text) {
     });
 }
 
+/// send stdin with mode: line, raw, escaped (\x03 for Ctrl-C) or eof
+/// sensitive input is redacted in logs and console
+export function api_input_mode (sid, input, mode, sensitive = false) {
+    return axios.post('/api/service/' + sid + '/input', { input: input, mode: mode, sensitive: sensitive });
+}
+
 export function api_output (sid) {
     return axios.get('/api/service/' + sid + '/output');
 }
 
+/// search live output, query: {query, regex, case_insensitive, streams, context, offset, limit}
+export function api_output_search (sid, query) {
+    return axios.post('/api/service/' + sid + '/output/search', query);
+}
+
 export function api_stop (sid) {
     return axios.post('/api/service/' + sid + '/stop');
 }
@@ -49,6 +64,26 @@ export function api_kill (sid) {
     return axios.post('/api/service/' + sid + '/kill');
 }
 
+export function api_restart (sid) {
+    return axios.post('/api/service/' + sid + '/restart');
+}
+
+export function api_pause (sid) {
+    return axios.post('/api/service/' + sid + '/pause');
+}
+
+export function api_resume (sid) {
+    return axios.post('/api/service/' + sid + '/resume');
+}
+
+export function api_signals (sid) {
+    return axios.get('/api/service/' + sid + '/signal');
+}
+
+export function api_signal (sid, signal) {
+    return axios.post('/api/service/' + sid + '/signal', {signal});
+}
+
 export function api_start (sid) {
     return axios.post('/api/service/' + sid + '/start');
 }
@@ -135,6 +170,20 @@ export function api_service_permissions (service) {
     return axios.get("/api/service/" + service + "/permissions");
 }
 
+/// mail notification subscription of current session
+export function api_usage (service) {
+    return axios.get("/api/service/" + service + "/usage");
+}
+
+export function api_get_notifications (service) {
+    return axios.get("/api/service/" + service + "/notifications");
+}
+
+/// set mail notification subscription of current session, see NotifyEvents
+export function api_set_notifications (service, events) {
+    return axios.post("/api/service/" + service + "/notifications", { events: events });
+}
+
 /// get latest logs for service
 export function api_log_latest (service,amount) {
     return axios.get("/api/service/" + service + "/log/latest/"+amount);
@@ -145,11 +194,26 @@ export function api_log_console (service,logid) {
     return axios.get("/api/service/" + service + "/log/console/"+logid);
 }
 
+/// search log console snapshot, same query as api_output_search
+export function api_log_console_search (service,logid,query) {
+    return axios.post("/api/service/" + service + "/log/console/"+logid+"/search", query);
+}
+
 /// get log details
 export function api_log_details (service,logid) {
     return axios.get("/api/service/" + service + "/log/details/"+logid);
 }
 
+/// get rotated console log files
+export function api_log_files (service) {
+    return axios.get("/api/service/" + service + "/log/files");
+}
+
+/// download url of rotated console log file
+export function url_log_file (service, file) {
+    return "/api/service/" + service + "/log/files/" + encodeURIComponent(file);
+}
+
 /// global permissions of current session
 export function api_global_permissions () {
     return axios.get("/api/");
@@ -168,12 +232,29 @@ export class Permissions {
     static KILL = 16;
     /// Inspect service log
     static LOG = 32;
+    /// Resource usage history
+    static STATS = 64;
+    /// Pause and resume service
+    static PAUSE = 128;
+    /// Send allowed signals
+    static SIGNAL = 256;
+    /// Restart service
+    static RESTART = 512;
 
     static hasFlag (input, flag) {
         return (input & flag) != 0;
     };
 }
 
+export class NotifyEvents {
+    /// Service crashed
+    static CRASH = 1;
+    /// Service keeps crashing after restarts
+    static RESTART_LOOP = 2;
+    /// Service failed to start
+    static START_FAILED = 4;
+}
+
 export class Log {
     static SystemStart = "SystemStartup";
     static KilledCmd = "ServiceCmdKilled";
@@ -186,6 +267,19 @@ export class Log {
     static StartCmd = "ServiceCmdStart";
     static Crash = "ServiceCrashed"; // (i32)
     static Input = "Stdin"; // string
+    static InputEof = "StdinEof";
+    static InputDenied = "StdinDenied"; // string
+    static Trigger = "OutputTrigger"; // string
+    static TriggerNotify = "OutputNotify"; // string
+    static LimitExceeded = "ServiceLimitExceeded"; // string
+    static OomKilled = "ServiceOomKilled";
+    static TimedOut = "ServiceTimedOut"; // seconds
+    static Silent = "OutputSilent"; // seconds
+    static MemoryRestart = "MemoryRestart"; // MB
+    static Paused = "ServicePaused";
+    static Resumed = "ServiceResumed";
+    static Signal = "Signal"; // string
+    static RestartCmd = "ServiceCmdRestart";
 }
 
 export function formatLog(entry) {
@@ -202,6 +296,11 @@ export function formatLog(entry) {
             case Log.Killed: return "Service killed";
             case Log.KilledCmd: return "Service kill by "+ entry.invoker.name;
             case Log.Started: return "Service started";
+            case Log.OomKilled: return "Service killed by OOM killer";
+            case Log.RestartCmd: return "Service restart by "+entry.invoker.name;
+            case Log.Paused: return "Service paused by "+entry.invoker.name;
+            case Log.Resumed: return "Service resumed by "+entry.invoker.name;
+            case Log.InputEof: return "Console input closed by "+entry.invoker.name;
             case Log.StartCmd:
                 if (entry.invoker)
                     return "Service start by "+ entry.invoker.name;
@@ -212,8 +311,20 @@ export function formatLog(entry) {
     } else {
         switch (Object.keys(entry.action)[0]) {
             case Log.StartFailure: return "Startup failure: "+entry.action[Log.StartFailure];
-            case Log.Crash: return "Service crashed, signal "+entry.action[Log.Crash];
+            case Log.Crash:
+                if (entry.exit && entry.exit.signal !== null)
+                    return "Service crashed by signal "+entry.exit.signal+(entry.exit.core_dumped ? " (core dumped)" : "");
+                else
+                    return "Service crashed, exit code "+entry.action[Log.Crash];
             case Log.Input: return "Console input by "+entry.invoker.name+": "+entry.action[Log.Input];
+            case Log.Trigger: return "Output trigger: "+entry.action[Log.Trigger];
+            case Log.TriggerNotify: return "Output notification: "+entry.action[Log.TriggerNotify];
+            case Log.Signal: return "Signal "+entry.action[Log.Signal]+" sent by "+entry.invoker.name;
+            case Log.TimedOut: return "Service stopped after max runtime of "+entry.action[Log.TimedOut]+"s";
+            case Log.Silent: return "No output for "+entry.action[Log.Silent]+"s";
+            case Log.MemoryRestart: return "Restart at memory usage of "+entry.action[Log.MemoryRestart]+" MB";
+            case Log.LimitExceeded: return "Service crashed, "+entry.action[Log.LimitExceeded];
+            case Log.InputDenied: return "Denied console input by "+entry.invoker.name+": "+entry.action[Log.InputDenied];
         }
     }
     console.error("Unknown log entry!",entry.action);
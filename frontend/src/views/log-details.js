@@ -7,6 +7,7 @@ import Button from "react-bootstrap/Button";
 import Loading from "../components/loading";
 import Error from "../components/error";
 import { api_service_permissions, Permissions, api_log_details, api_log_console, formatLog } from "../lib/Api";
+import { fmtDuration } from "../lib/time";
 import { Link } from "react-router-dom";
 
 export default class LogDetails extends React.Component {
@@ -113,6 +114,12 @@ export default class LogDetails extends React.Component {
                         <Row><Col>Date</Col><Col>{datetime.toLocaleDateString()}</Col></Row>
                         <Row><Col>Time</Col><Col>{datetime.toLocaleTimeString()}</Col></Row>
                         <Row><Col>Info</Col><Col>{formatLog(this.state.entry)}</Col></Row>
+                        {this.state.entry.exit && (<>
+                            <Row><Col>Signal</Col><Col>{this.state.entry.exit.signal === null ? "none" : this.state.entry.exit.signal}</Col></Row>
+                            <Row><Col>Core dumped</Col><Col>{this.state.entry.exit.core_dumped ? "yes" : "no"}</Col></Row>
+                            <Row><Col>Runtime</Col><Col>{fmtDuration(this.state.entry.exit.runtime_secs)}</Col></Row>
+                            <Row><Col>Restarts</Col><Col>{this.state.entry.exit.restarts}</Col></Row>
+                        </>)}
                         <Row id="output" className="d-flex flex-grow-1 flex-fill overflow-auto console-wrapper">
                             {console_perm ? (
                                 <Output data={this.state.output} />) : (
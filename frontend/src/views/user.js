@@ -344,6 +344,9 @@ export default class User extends React.Component {
                         <Form.Check type="checkbox"
                             checked={Permissions.hasFlag(perms, Permissions.KILL)}
                             flag={Permissions.KILL} onChange={this.setPermission} label="Kill service" />
+                        <Form.Check type="checkbox"
+                            checked={Permissions.hasFlag(perms, Permissions.RESTART)}
+                            flag={Permissions.RESTART} onChange={this.setPermission} label="Restart service" />
                         <Form.Check type="checkbox"
                             checked={Permissions.hasFlag(perms, Permissions.STDIN_ALL)}
                             flag={Permissions.STDIN_ALL} onChange={this.setPermission} label="Stdin input" />
@@ -353,6 +356,15 @@ export default class User extends React.Component {
                         <Form.Check type="checkbox"
                             checked={Permissions.hasFlag(perms, Permissions.LOG)}
                             flag={Permissions.LOG} onChange={this.setPermission} label="Log view" />
+                        <Form.Check type="checkbox"
+                            checked={Permissions.hasFlag(perms, Permissions.STATS)}
+                            flag={Permissions.STATS} onChange={this.setPermission} label="Resource usage" />
+                        <Form.Check type="checkbox"
+                            checked={Permissions.hasFlag(perms, Permissions.PAUSE)}
+                            flag={Permissions.PAUSE} onChange={this.setPermission} label="Pause service" />
+                        <Form.Check type="checkbox"
+                            checked={Permissions.hasFlag(perms, Permissions.SIGNAL)}
+                            flag={Permissions.SIGNAL} onChange={this.setPermission} label="Send signals" />
                     </Form>
                 </Modal.Body>
 
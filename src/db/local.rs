@@ -80,6 +80,8 @@ mod tree {
     pub const META: &str = "META";
     /// "UID_SID"<->ServicePerm
     pub const PERMISSION_SERVICE: &str = "PERMISSIONS_SERVICE";
+    /// "UID_SID"<->NotifyEvents
+    pub const NOTIFICATIONS: &str = "NOTIFICATIONS";
     /// session String<->UID
     pub const LOGINS: &str = "LOGINS";
     /// session String<->u64 time
@@ -94,8 +96,13 @@ mod tree {
 mod meta {
     /// UID - atomic counter for unique UID generation
     pub const USER_AUTO_ID: &str = "USER_AUTO_ID";
+    /// u32 - format version of LOG_ENTRIES values
+    pub const LOG_FORMAT: &str = "LOG_FORMAT";
 }
 
+/// Current format of LOG_ENTRIES values, 1 being [LogEntryV1](LogEntryV1)
+const LOG_FORMAT: u32 = 2;
+
 #[derive(Clone)]
 pub struct DB {
     db: Db,
@@ -103,7 +110,7 @@ pub struct DB {
 
 impl Default for DB {
     fn default() -> Self {
-        Self {
+        let db = Self {
             // TODO: this does NOT return but panic when the DB is already in use
             db: match open("db.sled") {
                 Err(e) => {
@@ -112,7 +119,12 @@ impl Default for DB {
                 }
                 Ok(v) => v,
             },
+        };
+        if let Err(e) = db.migrate() {
+            error!("Unable to migrate local DB: {}", e);
+            panic!("Unable to migrate local DB: {}", e);
         }
+        db
     }
 }
 
@@ -179,6 +191,31 @@ impl DB {
         error!("Out of UID generator retries!");
         Err(DBError::TooManyRetries(max).into())
     }
+    /// Upgrade data stored by older versions
+    fn migrate(&self) -> Result<()> {
+        let meta_tree = self.open_tree(tree::META)?;
+        let version = match meta_tree.get(meta::LOG_FORMAT)? {
+            Some(v) => deserialize::<u32>(&v)?,
+            None => 1,
+        };
+        if version < LOG_FORMAT {
+            let tree = self.open_tree(tree::LOG_ENTRIES)?;
+            let mut migrated = 0;
+            for entry in tree.iter() {
+                let (k, v) = entry?;
+                // already converted by an interrupted migration
+                if deserialize::<LogEntry>(&v).is_ok() {
+                    continue;
+                }
+                let entry: LogEntry = deserialize::<LogEntryV1>(&v)?.into();
+                tree.insert(k, ser!(entry))?;
+                migrated += 1;
+            }
+            meta_tree.insert(meta::LOG_FORMAT, ser!(LOG_FORMAT))?;
+            info!("Migrated {} log entries to format {}", migrated, LOG_FORMAT);
+        }
+        Ok(())
+    }
     /// Check if id is valid (taken)
     fn is_valid_uid(&self, id: UID) -> Result<bool> {
         Ok(self.open_tree(tree::USER)?.contains_key(ser!(id))?)
@@ -189,9 +226,11 @@ impl super::DBInterface for DB {
     fn new_temp() -> Self {
         let config = Config::default().temporary(true);
 
-        Self {
+        let db = Self {
             db: config.open().expect("Can't start local DB!"),
-        }
+        };
+        db.migrate().expect("Can't migrate local DB!");
+        db
     }
 
     fn get_root_id(&self) -> UID {
@@ -297,6 +336,39 @@ impl super::DBInterface for DB {
         Ok(())
     }
 
+    fn get_notifications(&self, id: UID, service: SID) -> Result<NotifyEvents> {
+        let v = self
+            .open_tree(tree::NOTIFICATIONS)?
+            .get(&DB::service_perm_key(id, service))?;
+        Ok(match v {
+            Some(v) => deserialize(&v)?,
+            None => NotifyEvents::empty(),
+        })
+    }
+
+    fn set_notifications(&self, id: UID, service: SID, events: NotifyEvents) -> Result<()> {
+        let tree = self.open_tree(tree::NOTIFICATIONS)?;
+        let key = DB::service_perm_key(id, service);
+        if events.is_empty() {
+            tree.remove(&key)?;
+        } else {
+            tree.insert(&key, ser!(events))?;
+        }
+        Ok(())
+    }
+
+    fn get_notification_subscribers(&self, service: SID) -> Result<Vec<(UID, NotifyEvents)>> {
+        let mut subscribers = Vec::new();
+        for v in self.open_tree(tree::NOTIFICATIONS)?.iter() {
+            let (key, value) = v?;
+            let (uid, sid) = DB::service_perm_key_reverse(&key)?;
+            if sid == service {
+                subscribers.push((uid, deserialize(&value)?));
+            }
+        }
+        Ok(subscribers)
+    }
+
     fn get_login(&self, session: &str, max_age: u32) -> Result<Option<ActiveLogin>> {
         Ok(match self.open_tree(tree::LOGINS)?.get(ser!(session))? {
             Some(v) => {
@@ -360,6 +432,14 @@ impl super::DBInterface for DB {
         Ok(deleted)
     }
 
+    fn count_logins(&self) -> Result<usize> {
+        Ok(self.open_tree(tree::LOGINS)?.len())
+    }
+
+    fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
     fn update_user(&self, user: FullUser) -> Result<()> {
         if !self.is_valid_uid(user.id)? {
             return Err(super::Error::InvalidUser(user.id));
@@ -396,6 +476,12 @@ impl super::DBInterface for DB {
         self.open_tree(tree::REL_MAIL_UID)?
             .remove(ser!(user.email))?;
         self.open_tree(tree::PERMISSION_SERVICE)?.remove(ser!(id))?;
+        let notifications = self.open_tree(tree::NOTIFICATIONS)?;
+        for v in
+            notifications.range(Self::service_perm_key(id, 0)..Self::service_perm_key(id + 1, 0))
+        {
+            notifications.remove(v?.0)?;
+        }
         let sessions = self.open_tree(tree::LOGINS)?;
         for val in sessions.iter() {
             let (key, val) = val?;
@@ -488,6 +574,7 @@ impl super::DBInterface for DB {
                 id: entry.log_id,
                 invoker,
                 console_log: entry.console_log,
+                exit: entry.exit,
             };
             return Ok(Some(entry));
         }
@@ -524,6 +611,7 @@ impl super::DBInterface for DB {
                 id: entry.log_id,
                 invoker,
                 console_log: entry.console_log,
+                exit: entry.exit,
             };
             entries.push(entry);
             if entries.len() >= limit {
@@ -605,6 +693,53 @@ mod test {
         assert_eq!(db.get_all_perm_service(1).unwrap(), map);
     }
 
+    #[test]
+    fn test_migrate_log_entries() {
+        let db = DB::new_temp();
+        let old = LogEntryV1 {
+            time: 1,
+            action: LogAction::ServiceCrashed(2),
+            invoker: None,
+            log_id: 100,
+            console_log: false,
+        };
+        db.open_tree(tree::LOG_ENTRIES)
+            .unwrap()
+            .insert(DB::ser_key(&(1 as SID, 100 as LogID)), ser!(old))
+            .unwrap();
+        db.open_tree(tree::META)
+            .unwrap()
+            .remove(meta::LOG_FORMAT)
+            .unwrap();
+        db.migrate().unwrap();
+
+        let exit = ExitDetails {
+            signal: Some(11),
+            core_dumped: true,
+            runtime_secs: 60,
+            restarts: 1,
+        };
+        db.insert_log_entry(
+            1,
+            NewLogEntry::new(LogAction::ServiceCrashed(0), None).exit(exit.clone()),
+            None,
+        )
+        .unwrap();
+        // a repeated migration keeps converted entries
+        db.open_tree(tree::META)
+            .unwrap()
+            .remove(meta::LOG_FORMAT)
+            .unwrap();
+        db.migrate().unwrap();
+
+        let entries = db.service_log_limited(1, 10).unwrap();
+        assert_eq!(2, entries.len());
+        let (old, new): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.id == 100);
+        assert_eq!(LogAction::ServiceCrashed(2), old[0].action);
+        assert_eq!(None, old[0].exit);
+        assert_eq!(Some(exit), new[0].exit);
+    }
+
     /// Assert that our serialization works as intended
     ///
     /// This also revealed that we need to enforce big endian for this to work
@@ -58,6 +58,12 @@ pub trait DBInterface: Sized {
     fn get_all_perm_service(&self, id: UID) -> Result<HashMap<SID, ServicePerm>>;
     /// Update user permissions for service
     fn set_perm_service(&self, id: UID, service: SID, new_perms: ServicePerm) -> Result<()>;
+    /// Get mail notification subscription of user for a service
+    fn get_notifications(&self, id: UID, service: SID) -> Result<NotifyEvents>;
+    /// Update mail notification subscription of user for a service
+    fn set_notifications(&self, id: UID, service: SID, events: NotifyEvents) -> Result<()>;
+    /// Get all users subscribed to notifications of a service
+    fn get_notification_subscribers(&self, service: SID) -> Result<Vec<(UID, NotifyEvents)>>;
     /// Get session login if not older than max_age
     fn get_login(&self, session: &str, max_age: u32) -> Result<Option<ActiveLogin>>;
     /// Set session login
@@ -68,6 +74,10 @@ pub trait DBInterface: Sized {
     fn update_login(&self, session: &str) -> Result<()>;
     /// Delete logins older than max_age
     fn delete_old_logins(&self, max_age: u32) -> Result<usize>;
+    /// Amount of stored session logins, including outdated ones not yet cleaned up
+    fn count_logins(&self) -> Result<usize>;
+    /// Size of the DB on disk in bytes
+    fn size_on_disk(&self) -> Result<u64>;
     /// Get (reserved) root UID
     fn get_root_id(&self) -> UID;
     /// Insert new service log entry, returns log entry ID
@@ -271,6 +281,7 @@ mod test {
         };
         db.set_login(&sess, Some(state.clone())).unwrap();
         assert_eq!(Some(state), db.get_login(&sess, 12).unwrap());
+        assert_eq!(1, db.count_logins().unwrap());
         db.set_login(&sess, None).unwrap();
         assert_eq!(None, db.get_login(&sess, 12).unwrap());
     }
@@ -304,6 +315,7 @@ mod test {
         sleep(Duration::from_millis(1_000));
         assert_eq!(None, db.get_login(&sess, 0).unwrap());
         assert_eq!(1, db.delete_old_logins(0).unwrap());
+        assert_eq!(0, db.count_logins().unwrap());
     }
 
     #[test]
@@ -321,6 +333,40 @@ mod test {
         assert_eq!(perm, db.get_perm_service(full_user.id, 1).unwrap());
     }
 
+    #[test]
+    fn test_notifications() {
+        let db = gen_db();
+        let (_, user_a) = create_user(&db);
+        let (_, user_b) = create_user(&db);
+        assert!(db.get_notifications(user_a.id, 1).unwrap().is_empty());
+        db.set_notifications(user_a.id, 1, NotifyEvents::CRASH)
+            .unwrap();
+        db.set_notifications(user_b.id, 1, NotifyEvents::all())
+            .unwrap();
+        db.set_notifications(user_b.id, 2, NotifyEvents::RESTART_LOOP)
+            .unwrap();
+        assert_eq!(
+            NotifyEvents::CRASH,
+            db.get_notifications(user_a.id, 1).unwrap()
+        );
+        let mut subscribers = db.get_notification_subscribers(1).unwrap();
+        subscribers.sort_by_key(|(uid, _)| *uid);
+        assert_eq!(
+            vec![
+                (user_a.id, NotifyEvents::CRASH),
+                (user_b.id, NotifyEvents::all())
+            ],
+            subscribers
+        );
+        // unsubscribe
+        db.set_notifications(user_a.id, 1, NotifyEvents::empty())
+            .unwrap();
+        assert_eq!(1, db.get_notification_subscribers(1).unwrap().len());
+        // removed with user
+        db.delete_user(user_b.id).unwrap();
+        assert!(db.get_notification_subscribers(2).unwrap().is_empty());
+    }
+
     #[test]
     fn test_perm_admin() {
         let db = gen_db();
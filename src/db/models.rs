@@ -2,6 +2,7 @@ pub use crate::web::models::UserMin;
 use bitflags::bitflags;
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 /// User ID
 pub type UID = i32;
@@ -36,12 +37,76 @@ pub struct FullUser {
 
 pub type ConsoleOutput = Vec<ConsoleType<String>>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ConsoleType<T> {
     Stdin(T),
     Stdout(T),
     Stderr(T),
     State(T),
+    /// Stdout with parsed ANSI styles
+    StdoutStyled(Vec<StyledSpan<T>>),
+    /// Stderr with parsed ANSI styles
+    StderrStyled(Vec<StyledSpan<T>>),
+}
+
+impl<T> ConsoleType<T> {
+    /// Convert contained text
+    pub fn map<U, F: Fn(&T) -> U>(&self, f: F) -> ConsoleType<U> {
+        match self {
+            ConsoleType::Stdin(v) => ConsoleType::Stdin(f(v)),
+            ConsoleType::Stdout(v) => ConsoleType::Stdout(f(v)),
+            ConsoleType::Stderr(v) => ConsoleType::Stderr(f(v)),
+            ConsoleType::State(v) => ConsoleType::State(f(v)),
+            ConsoleType::StdoutStyled(v) => {
+                ConsoleType::StdoutStyled(v.iter().map(|s| s.map(&f)).collect())
+            }
+            ConsoleType::StderrStyled(v) => {
+                ConsoleType::StderrStyled(v.iter().map(|s| s.map(&f)).collect())
+            }
+        }
+    }
+}
+
+impl ConsoleType<String> {
+    /// Plain text of the line, styled spans are joined
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            ConsoleType::Stdin(v)
+            | ConsoleType::Stdout(v)
+            | ConsoleType::Stderr(v)
+            | ConsoleType::State(v) => Cow::Borrowed(v),
+            ConsoleType::StdoutStyled(spans) | ConsoleType::StderrStyled(spans) => {
+                Cow::Owned(spans.iter().map(|s| s.text.as_str()).collect())
+            }
+        }
+    }
+}
+
+/// Text segment of a console line with SGR attributes
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StyledSpan<T> {
+    pub text: T,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+}
+
+impl<T> StyledSpan<T> {
+    fn map<U, F: Fn(&T) -> U>(&self, f: F) -> StyledSpan<U> {
+        StyledSpan {
+            text: f(&self.text),
+            fg: self.fg,
+            bg: self.bg,
+            bold: self.bold,
+        }
+    }
+}
+
+/// Terminal color, palette index (0-255) or 24bit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Color {
+    Palette(u8),
+    Rgb(u8, u8, u8),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,6 +170,14 @@ bitflags! {
         const KILL   = 0b0001_0000;
         /// Log inspection
         const LOG    = 0b0010_0000;
+        /// Resource usage history
+        const STATS  = 0b0100_0000;
+        /// Pause and resume service
+        const PAUSE  = 0b1000_0000;
+        /// Send allowed signals
+        const SIGNAL = 0b1_0000_0000;
+        /// Restart service
+        const RESTART = 0b10_0000_0000;
     }
 }
 
@@ -114,6 +187,19 @@ impl Default for ServicePerm {
     }
 }
 
+bitflags! {
+    /// Mail notification subscription of a user for a service
+    #[derive(Serialize, Deserialize)]
+    pub struct NotifyEvents: u32 {
+        /// Service crashed
+        const CRASH        = 0b0001;
+        /// Service keeps crashing after restarts
+        const RESTART_LOOP = 0b0010;
+        /// Service failed to start
+        const START_FAILED = 0b0100;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[cfg_attr(test, derive(Clone, PartialEq))]
 pub struct ActiveLogin {
@@ -138,6 +224,7 @@ pub struct LogEntryResolved {
     pub invoker: Option<Invoker>,
     pub id: LogID,
     pub console_log: bool,
+    pub exit: Option<ExitDetails>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -164,6 +251,8 @@ pub struct LogEntry {
     pub log_id: LogID,
     /// true if console log exists
     pub console_log: bool,
+    /// Set for process exits
+    pub exit: Option<ExitDetails>,
 }
 
 impl LogEntry {
@@ -174,16 +263,53 @@ impl LogEntry {
             invoker: entry.invoker,
             log_id,
             console_log: console_log,
+            exit: entry.exit,
+        }
+    }
+}
+
+/// LogEntry format before exit details, only for migration
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogEntryV1 {
+    pub time: Date,
+    pub action: LogAction,
+    pub invoker: Option<UID>,
+    pub log_id: LogID,
+    pub console_log: bool,
+}
+
+impl From<LogEntryV1> for LogEntry {
+    fn from(entry: LogEntryV1) -> Self {
+        Self {
+            time: entry.time,
+            action: entry.action,
+            invoker: entry.invoker,
+            log_id: entry.log_id,
+            console_log: entry.console_log,
+            exit: None,
         }
     }
 }
 
+/// Details of a process exit
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExitDetails {
+    /// Terminating signal, None for normal exits
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    /// Run duration in seconds
+    pub runtime_secs: u64,
+    /// Automatic restarts since daemon start
+    pub restarts: u64,
+}
+
 /// Log entry without unique key, which is created by DB
 #[derive(Debug)]
 pub struct NewLogEntry {
     pub time: Date,
     pub action: LogAction,
     pub invoker: Option<UID>,
+    pub exit: Option<ExitDetails>,
 }
 
 impl NewLogEntry {
@@ -192,12 +318,18 @@ impl NewLogEntry {
             time: Utc::now().timestamp_millis(), // TODO
             action,
             invoker,
+            exit: None,
         }
     }
+    /// Attach details of a process exit
+    pub fn exit(mut self, exit: ExitDetails) -> Self {
+        self.exit = Some(exit);
+        self
+    }
 }
 
 /// Logged action
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum LogAction {
     SystemStartup,
     ServiceCmdKilled,
@@ -210,6 +342,85 @@ pub enum LogAction {
     ServiceCmdStart,
     ServiceCrashed(i32),
     Stdin(String),
+    StdinEof,
+    StdinDenied(String),
+    OutputTrigger(String),
+    OutputNotify(String),
+    /// Crashed by exceeding a resource limit
+    ServiceLimitExceeded(String),
+    /// Killed by the OOM killer of its cgroup
+    ServiceOomKilled,
+    ServicePaused,
+    ServiceResumed,
+    /// Signal sent to the service
+    Signal(String),
+    ServiceCmdRestart,
+    /// Stopped after exceeding its max runtime in seconds
+    ServiceTimedOut(u64),
+    /// No output for the given seconds
+    OutputSilent(u64),
+    /// Restarted for memory usage in MB above the threshold
+    MemoryRestart(u64),
+}
+
+impl LogAction {
+    /// Names of all variants, as returned by [kind](LogAction::kind)
+    pub const KINDS: &'static [&'static str] = &[
+        "SystemStartup",
+        "ServiceCmdKilled",
+        "ServiceKilled",
+        "ServiceCmdStop",
+        "ServiceEnded",
+        "ServiceStopped",
+        "ServiceStartFailed",
+        "ServiceStarted",
+        "ServiceCmdStart",
+        "ServiceCrashed",
+        "Stdin",
+        "StdinEof",
+        "StdinDenied",
+        "OutputTrigger",
+        "OutputNotify",
+        "ServiceLimitExceeded",
+        "ServiceOomKilled",
+        "ServicePaused",
+        "ServiceResumed",
+        "Signal",
+        "ServiceCmdRestart",
+        "ServiceTimedOut",
+        "OutputSilent",
+        "MemoryRestart",
+    ];
+
+    /// Variant name without data
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LogAction::SystemStartup => "SystemStartup",
+            LogAction::ServiceCmdKilled => "ServiceCmdKilled",
+            LogAction::ServiceKilled => "ServiceKilled",
+            LogAction::ServiceCmdStop => "ServiceCmdStop",
+            LogAction::ServiceEnded => "ServiceEnded",
+            LogAction::ServiceStopped => "ServiceStopped",
+            LogAction::ServiceStartFailed(_) => "ServiceStartFailed",
+            LogAction::ServiceStarted => "ServiceStarted",
+            LogAction::ServiceCmdStart => "ServiceCmdStart",
+            LogAction::ServiceCrashed(_) => "ServiceCrashed",
+            LogAction::Stdin(_) => "Stdin",
+            LogAction::StdinEof => "StdinEof",
+            LogAction::StdinDenied(_) => "StdinDenied",
+            LogAction::OutputTrigger(_) => "OutputTrigger",
+            LogAction::OutputNotify(_) => "OutputNotify",
+            LogAction::ServiceLimitExceeded(_) => "ServiceLimitExceeded",
+            LogAction::ServiceOomKilled => "ServiceOomKilled",
+            LogAction::ServicePaused => "ServicePaused",
+            LogAction::ServiceResumed => "ServiceResumed",
+            LogAction::Signal(_) => "Signal",
+            LogAction::ServiceCmdRestart => "ServiceCmdRestart",
+            LogAction::ServiceTimedOut(_) => "ServiceTimedOut",
+            LogAction::OutputSilent(_) => "OutputSilent",
+            LogAction::MemoryRestart(_) => "MemoryRestart",
+        }
+    }
 }
 
 pub type Date = i64;
@@ -0,0 +1,212 @@
+//! ANSI SGR parsing into styled spans
+use crate::db::models::{Color, StyledSpan};
+use std::convert::TryFrom;
+use std::mem;
+use vte::{Parser, Perform};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+}
+
+struct Performer {
+    style: Style,
+    text: String,
+    spans: Vec<StyledSpan<Vec<u8>>>,
+}
+
+impl Performer {
+    /// Finish current span
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            let text = mem::take(&mut self.text);
+            self.spans.push(StyledSpan {
+                text: text.into_bytes(),
+                fg: self.style.fg,
+                bg: self.style.bg,
+                bold: self.style.bold,
+            });
+        }
+    }
+
+    fn sgr(&mut self, params: &[i64]) {
+        let mut style = self.style;
+        if params.is_empty() {
+            style = Style::default();
+        }
+        let mut iter = params.iter();
+        while let Some(p) = iter.next() {
+            match *p {
+                0 => style = Style::default(),
+                1 => style.bold = true,
+                22 => style.bold = false,
+                30..=37 => style.fg = Some(Color::Palette((p - 30) as u8)),
+                38 => style.fg = extended(&mut iter),
+                39 => style.fg = None,
+                40..=47 => style.bg = Some(Color::Palette((p - 40) as u8)),
+                48 => style.bg = extended(&mut iter),
+                49 => style.bg = None,
+                90..=97 => style.fg = Some(Color::Palette((p - 90 + 8) as u8)),
+                100..=107 => style.bg = Some(Color::Palette((p - 100 + 8) as u8)),
+                _ => (),
+            }
+        }
+        if style != self.style {
+            self.flush();
+            self.style = style;
+        }
+    }
+}
+
+fn channel(v: Option<&i64>) -> Option<u8> {
+    v.and_then(|v| u8::try_from(*v).ok())
+}
+
+/// Parse 256 color or truecolor parameters following 38/48
+fn extended(iter: &mut std::slice::Iter<i64>) -> Option<Color> {
+    match iter.next() {
+        Some(5) => channel(iter.next()).map(Color::Palette),
+        Some(2) => {
+            let r = channel(iter.next())?;
+            let g = channel(iter.next())?;
+            let b = channel(iter.next())?;
+            Some(Color::Rgb(r, g, b))
+        }
+        _ => None,
+    }
+}
+
+impl Perform for Performer {
+    fn print(&mut self, c: char) {
+        self.text.push(c);
+    }
+    fn execute(&mut self, _byte: u8) {}
+    fn hook(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool) {}
+    fn put(&mut self, _byte: u8) {}
+    fn unhook(&mut self) {}
+    fn osc_dispatch(&mut self, _params: &[&[u8]]) {}
+    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, c: char) {
+        if c == 'm' && intermediates.is_empty() {
+            self.sgr(params);
+        }
+    }
+    fn esc_dispatch(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool, _byte: u8) {}
+}
+
+/// Stateful parser for one output stream
+///
+/// Styles carry over to following lines until reset, like in a terminal.
+/// All escape sequences except SGR are dropped.
+pub struct StyleParser {
+    parser: Parser,
+    performer: Performer,
+}
+
+impl StyleParser {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            performer: Performer {
+                style: Style::default(),
+                text: String::new(),
+                spans: Vec::new(),
+            },
+        }
+    }
+
+    /// Parse one line into spans
+    pub fn line(&mut self, data: &[u8]) -> Vec<StyledSpan<Vec<u8>>> {
+        for b in data {
+            self.parser.advance(&mut self.performer, *b);
+        }
+        self.performer.flush();
+        mem::take(&mut self.performer.spans)
+    }
+}
+
+impl Default for StyleParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn span(text: &str, fg: Option<Color>, bg: Option<Color>, bold: bool) -> StyledSpan<Vec<u8>> {
+        StyledSpan {
+            text: text.as_bytes().to_vec(),
+            fg,
+            bg,
+            bold,
+        }
+    }
+
+    #[test]
+    fn test_plain() {
+        let mut parser = StyleParser::new();
+        assert_eq!(
+            vec![span("foo bar", None, None, false)],
+            parser.line(b"foo bar")
+        );
+        assert!(parser.line(b"").is_empty());
+    }
+
+    #[test]
+    fn test_colors() {
+        let mut parser = StyleParser::new();
+        assert_eq!(
+            vec![
+                span("[", None, None, false),
+                span("WARN", Some(Color::Palette(3)), None, true),
+                span("] msg", None, None, false),
+            ],
+            parser.line(b"[\x1b[1;33mWARN\x1b[0m] msg")
+        );
+        assert_eq!(
+            vec![
+                span(
+                    "a",
+                    Some(Color::Palette(208)),
+                    Some(Color::Palette(9)),
+                    false
+                ),
+                span(
+                    "b",
+                    Some(Color::Rgb(1, 2, 3)),
+                    Some(Color::Palette(9)),
+                    false
+                ),
+            ],
+            parser.line(b"\x1b[38;5;208;101ma\x1b[38;2;1;2;3mb\x1b[m")
+        );
+    }
+
+    #[test]
+    fn test_carry_over() {
+        let mut parser = StyleParser::new();
+        assert_eq!(
+            vec![span("red", Some(Color::Palette(1)), None, false)],
+            parser.line(b"\x1b[31mred")
+        );
+        assert_eq!(
+            vec![
+                span("still", Some(Color::Palette(1)), None, false),
+                span(" reset", None, None, false),
+            ],
+            parser.line(b"still\x1b[39m reset")
+        );
+    }
+
+    #[test]
+    fn test_strip_other() {
+        let mut parser = StyleParser::new();
+        assert_eq!(
+            vec![span("text", None, None, false)],
+            parser.line(b"\x1b[2K\x1b]0;title\x07te\x1b[1Gxt")
+        );
+    }
+}
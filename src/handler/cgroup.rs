@@ -0,0 +1,182 @@
+//! cgroup v2 groups for service process trees
+//!
+//! Each service gets a child group below a delegated subtree, which has to be
+//! writable by SD. SD itself must not run in that subtree root, as cgroup v2
+//! doesn't allow processes in groups with enabled controllers for children.
+use crate::db::models::SID;
+use crate::settings::CgroupLimits;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Controllers enabled for service groups
+const CONTROLLERS: &str = "+memory +cpu +pids";
+
+/// Usage counters of a service group
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CgroupUsage {
+    pub memory_bytes: u64,
+    /// CPU time in microseconds
+    pub cpu_usec: u64,
+    pub pids: u64,
+}
+
+/// Group of a service
+pub struct Cgroup {
+    path: PathBuf,
+    /// OOM kills at start of the run
+    oom_kills: u64,
+}
+
+/// Value of a key in flat keyed files like memory.events or cpu.stat
+pub fn parse_keyed(content: &str, key: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        if parts.next()? == key {
+            parts.next()?.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+fn limit(value: Option<u64>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => String::from("max"),
+    }
+}
+
+fn read_value(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl Cgroup {
+    /// Create or reuse the group of a service and apply its limits
+    pub fn setup(root: &Path, id: SID, limits: &CgroupLimits) -> io::Result<Self> {
+        // already enabled controllers are accepted again
+        fs::write(root.join("cgroup.subtree_control"), CONTROLLERS)?;
+        let path = root.join(format!("service-{}", id));
+        if !path.is_dir() {
+            fs::create_dir(&path)?;
+        }
+        fs::write(path.join("memory.max"), limit(limits.memory_max))?;
+        let cpu_max = limits.cpu_max.as_ref().map_or("max", |v| v.as_str());
+        fs::write(path.join("cpu.max"), cpu_max)?;
+        fs::write(path.join("pids.max"), limit(limits.pids_max))?;
+        let mut cgroup = Self { path, oom_kills: 0 };
+        cgroup.oom_kills = cgroup.read_oom_kills();
+        Ok(cgroup)
+    }
+
+    /// Open `cgroup.procs` for [join](join) in the child
+    pub fn procs(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    fn read_oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|v| parse_keyed(&v, "oom_kill"))
+            .unwrap_or(0)
+    }
+
+    /// Whether the OOM killer hit the group since setup
+    pub fn oom_killed(&self) -> bool {
+        self.read_oom_kills() > self.oom_kills
+    }
+
+    /// Freeze or thaw all processes of the group
+    pub fn freeze(&self, frozen: bool) -> io::Result<()> {
+        fs::write(
+            self.path.join("cgroup.freeze"),
+            if frozen { "1" } else { "0" },
+        )
+    }
+
+    pub fn usage(&self) -> io::Result<CgroupUsage> {
+        let cpu = fs::read_to_string(self.path.join("cpu.stat"))?;
+        Ok(CgroupUsage {
+            memory_bytes: read_value(&self.path.join("memory.current"))?,
+            cpu_usec: parse_keyed(&cpu, "usage_usec").unwrap_or(0),
+            pids: read_value(&self.path.join("pids.current"))?,
+        })
+    }
+}
+
+/// Move the calling process into the group of `procs`
+///
+/// Runs in the child between fork and exec, so it must not allocate.
+#[cfg(unix)]
+pub fn join(procs: &File) -> io::Result<()> {
+    // 0 refers to the writing process
+    let ret = unsafe { libc::write(procs.as_raw_fd(), b"0".as_ptr() as *const libc::c_void, 1) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_keyed() {
+        let events = "low 0\nhigh 0\nmax 12\noom 2\noom_kill 1\n";
+        assert_eq!(Some(1), parse_keyed(events, "oom_kill"));
+        assert_eq!(Some(2), parse_keyed(events, "oom"));
+        assert_eq!(None, parse_keyed(events, "oom_group_kill"));
+    }
+
+    #[test]
+    fn test_setup() {
+        let root = tempdir().unwrap();
+        let limits = CgroupLimits {
+            memory_max: Some(1024),
+            cpu_max: Some("50000 100000".to_owned()),
+            pids_max: None,
+        };
+        let cgroup = Cgroup::setup(root.path(), 3, &limits).unwrap();
+        let dir = root.path().join("service-3");
+        let read = |file| fs::read_to_string(dir.join(file)).unwrap();
+        assert_eq!(
+            CONTROLLERS,
+            fs::read_to_string(root.path().join("cgroup.subtree_control")).unwrap()
+        );
+        assert_eq!("1024", read("memory.max"));
+        assert_eq!("50000 100000", read("cpu.max"));
+        assert_eq!("max", read("pids.max"));
+
+        assert!(!cgroup.oom_killed());
+        fs::write(dir.join("memory.events"), "oom 1\noom_kill 1\n").unwrap();
+        assert!(cgroup.oom_killed());
+        // baseline of a new run
+        let cgroup = Cgroup::setup(root.path(), 3, &limits).unwrap();
+        assert!(!cgroup.oom_killed());
+
+        cgroup.freeze(true).unwrap();
+        assert_eq!("1", read("cgroup.freeze"));
+
+        fs::write(dir.join("memory.current"), "4096\n").unwrap();
+        fs::write(dir.join("cpu.stat"), "usage_usec 1500\nuser_usec 1000\n").unwrap();
+        fs::write(dir.join("pids.current"), "3\n").unwrap();
+        assert_eq!(
+            CgroupUsage {
+                memory_bytes: 4096,
+                cpu_usec: 1500,
+                pids: 3,
+            },
+            cgroup.usage().unwrap()
+        );
+    }
+}
@@ -129,6 +129,22 @@ pub enum ControllerError {
     DBError(db::Error),
     #[fail(display = "Service has no soft-stop parameter")]
     NoSoftStop,
+    #[fail(display = "Invalid log file: {}", _0)]
+    InvalidLogFile(String),
+    #[fail(display = "Log file IO error: {}", _0)]
+    LogFileIOError(::std::io::Error),
+    #[fail(display = "Input denied by stdin policy")]
+    StdinDenied,
+    #[fail(display = "Service is paused!")]
+    ServicePaused,
+    #[fail(display = "Service is not paused!")]
+    ServiceNotPaused,
+    #[fail(display = "Unable to signal process: {}", _0)]
+    SignalIOError(::std::io::Error),
+    #[fail(display = "Signal not allowed: {}", _0)]
+    SignalDenied(String),
+    #[fail(display = "Restart already in progress!")]
+    RestartPending,
 }
 
 impl From<db::Error> for ControllerError {
@@ -156,13 +172,23 @@ impl ResponseError for ControllerError {
                 HttpResponse::BadRequest().body("invalid instance")
             }
             ControllerError::InvalidLog(_) => HttpResponse::BadRequest().body("invalid log"),
+            ControllerError::InvalidLogFile(_) => HttpResponse::NotFound().body("invalid log file"),
             ControllerError::ServiceRunning => {
                 HttpResponse::Conflict().body("Instance already running!")
             }
             ControllerError::ServiceStopped => {
                 HttpResponse::Conflict().body("Instance not running!")
             }
+            ControllerError::ServicePaused => HttpResponse::Conflict().body("Instance paused!"),
+            ControllerError::ServiceNotPaused => {
+                HttpResponse::Conflict().body("Instance not paused!")
+            }
+            ControllerError::RestartPending => {
+                HttpResponse::Conflict().body("Restart already in progress!")
+            }
             ControllerError::UserError(u) => u.error_response(),
+            ControllerError::StdinDenied => HttpResponse::Forbidden().body("stdin denied"),
+            ControllerError::SignalDenied(_) => HttpResponse::Forbidden().body("signal denied"),
             ControllerError::BrokenPipe => HttpResponse::InternalServerError().body("Broken pipe!"),
             v => {
                 error!("{}", v);
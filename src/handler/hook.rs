@@ -0,0 +1,153 @@
+//! Hook commands run around service starts and stops
+use crate::settings::{Hook, Service};
+use actix_rt::time::timeout;
+use futures::future;
+use std::io;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+#[derive(Fail, Debug)]
+pub enum HookError {
+    #[fail(display = "can't run hook: {}", _0)]
+    IOError(#[cause] io::Error),
+    #[fail(display = "timed out after {}s", _0)]
+    Timeout(u64),
+    #[fail(display = "exited with {}", _0)]
+    Failed(ExitStatus),
+}
+
+/// Point in the service lifecycle a hook runs at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookStage {
+    PreStart,
+    PostStart,
+    /// Stop afterwards, with kill fallback if true
+    PreStop(bool),
+    /// Start again afterwards if set, true for restart commands
+    PostStop(Option<bool>),
+}
+
+impl HookStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookStage::PreStart => "pre_start",
+            HookStage::PostStart => "post_start",
+            HookStage::PreStop(_) => "pre_stop",
+            HookStage::PostStop(_) => "post_stop",
+        }
+    }
+
+    /// Configured hook of the service for this stage
+    pub fn hook(self, service: &Service) -> Option<&Hook> {
+        match self {
+            HookStage::PreStart => service.pre_start.as_ref(),
+            HookStage::PostStart => service.post_start.as_ref(),
+            HookStage::PreStop(_) => service.pre_stop.as_ref(),
+            HookStage::PostStop(_) => service.post_stop.as_ref(),
+        }
+    }
+}
+
+/// Pass output lines of the hook to `output`
+async fn read_lines<R, F>(reader: R, output: F)
+where
+    R: AsyncRead + Unpin,
+    F: Fn(String),
+{
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while let Ok(n) = reader.read_until(b'\n', &mut line).await {
+        if n == 0 {
+            break;
+        }
+        output(
+            String::from_utf8_lossy(&line)
+                .trim_end_matches(&['\n', '\r'][..])
+                .to_owned(),
+        );
+        line.clear();
+    }
+}
+
+/// Run hook in `dir` with additional `envs`, killing it on timeout
+///
+/// Stdout and stderr lines are passed to `output`.
+pub async fn run<F>(
+    hook: Hook,
+    dir: PathBuf,
+    envs: Vec<(&'static str, String)>,
+    output: F,
+) -> Result<(), HookError>
+where
+    F: Fn(String) + Clone,
+{
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .envs(envs)
+        .current_dir(dir)
+        .kill_on_drop(true)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(HookError::IOError)?;
+    let stdout = read_lines(child.stdout.take().unwrap(), output.clone());
+    let stderr = read_lines(child.stderr.take().unwrap(), output);
+    let finished = future::join3(stdout, stderr, child);
+    match timeout(Duration::from_secs(hook.timeout_secs), finished).await {
+        Err(_) => Err(HookError::Timeout(hook.timeout_secs)),
+        Ok((_, _, Err(e))) => Err(HookError::IOError(e)),
+        Ok((_, _, Ok(status))) if status.success() => Ok(()),
+        Ok((_, _, Ok(status))) => Err(HookError::Failed(status)),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use actix::System;
+    use std::sync::{Arc, Mutex};
+
+    fn hook(script: &str, timeout_secs: u64) -> Hook {
+        Hook {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+            timeout_secs,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let mut sys = System::new("test");
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let lines_c = lines.clone();
+        let output = move |line| lines_c.lock().unwrap().push(line);
+        sys.block_on(run(
+            hook("pwd; echo err $SD_INSTANCE >&2", 5),
+            "/".into(),
+            vec![("SD_INSTANCE", "2".to_owned())],
+            output.clone(),
+        ))
+        .unwrap();
+        let mut lines_r = lines.lock().unwrap().clone();
+        lines_r.sort();
+        assert_eq!(vec!["/".to_owned(), "err 2".to_owned()], lines_r);
+
+        match sys.block_on(run(
+            hook("exit 3", 5),
+            "/".into(),
+            Vec::new(),
+            output.clone(),
+        )) {
+            Err(HookError::Failed(status)) => assert_eq!(Some(3), status.code()),
+            v => panic!("Expected Failed error got {:?}", v),
+        }
+        match sys.block_on(run(hook("sleep 10", 1), "/".into(), Vec::new(), output)) {
+            Err(HookError::Timeout(1)) => (),
+            v => panic!("Expected Timeout error got {:?}", v),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Persistent on-disk console log with rotation
+use crate::db::models::{ConsoleType, Date, SID};
+use crate::settings::LogFile;
+use chrono::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Folder for console log files, relative to the working directory
+pub const LOG_DIR: &str = "logs";
+const SUFFIX: &str = ".log";
+const SUFFIX_GZ: &str = ".log.gz";
+
+/// Rotated log file, as listed by the API
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LogFileInfo {
+    pub name: String,
+    pub size: u64,
+    /// Last modification time, unix millis
+    pub modified: Date,
+}
+
+/// Writes console lines of a service into `<dir>/<sid>-<name>.log`
+pub struct LogFileWriter {
+    dir: PathBuf,
+    base: String,
+    settings: LogFile,
+    file: Option<File>,
+    size: u64,
+    day: NaiveDate,
+}
+
+/// File name base for a service, `<sid>-<name>` with a filesystem safe name
+pub fn file_base(sid: SID, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{}-{}", sid, name)
+}
+
+impl LogFileWriter {
+    pub fn new(dir: PathBuf, sid: SID, name: &str, settings: LogFile) -> Self {
+        Self {
+            dir,
+            base: file_base(sid, name),
+            settings,
+            file: None,
+            size: 0,
+            day: Local::today().naive_local(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}{}", self.base, SUFFIX))
+    }
+
+    /// Open current log file for appending, if not already done
+    fn open(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path())?;
+            let meta = file.metadata()?;
+            self.size = meta.len();
+            if self.size > 0 {
+                let modified: DateTime<Local> = meta.modified()?.into();
+                self.day = modified.naive_local().date();
+            } else {
+                self.day = Local::today().naive_local();
+            }
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    /// Write console line, rotates beforehand if required
+    pub fn write_line(&mut self, line: &ConsoleType<Vec<u8>>) -> io::Result<()> {
+        let now = Local::now();
+        let mut entry = Vec::new();
+        let kind = match line {
+            ConsoleType::Stdin(_) => "stdin",
+            ConsoleType::Stdout(_) | ConsoleType::StdoutStyled(_) => "stdout",
+            ConsoleType::Stderr(_) | ConsoleType::StderrStyled(_) => "stderr",
+            ConsoleType::State(_) => "state",
+        };
+        write!(entry, "{} [{}] ", now.format("%Y-%m-%d %H:%M:%S"), kind)?;
+        match line {
+            ConsoleType::Stdin(v)
+            | ConsoleType::Stdout(v)
+            | ConsoleType::Stderr(v)
+            | ConsoleType::State(v) => entry.extend_from_slice(v),
+            ConsoleType::StdoutStyled(spans) | ConsoleType::StderrStyled(spans) => {
+                for span in spans {
+                    entry.extend_from_slice(&span.text);
+                }
+            }
+        }
+        if entry.last() != Some(&b'\n') {
+            entry.push(b'\n');
+        }
+
+        self.open()?;
+        if self.needs_rotation(entry.len() as u64, now.naive_local().date()) {
+            self.rotate(now)?;
+        }
+        let file = self.open()?;
+        file.write_all(&entry)?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    fn needs_rotation(&self, len: u64, today: NaiveDate) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        if self.settings.daily && today != self.day {
+            return true;
+        }
+        match self.settings.max_size {
+            Some(max) => self.size + len > max,
+            None => false,
+        }
+    }
+
+    /// Move current file away, compress and prune old files in the background
+    fn rotate(&mut self, now: DateTime<Local>) -> io::Result<()> {
+        self.file = None;
+        // sequence number to keep names unique and sortable on multiple rotations per second
+        let stamp = now.format("%Y%m%d-%H%M%S");
+        let mut i = 0;
+        let target = loop {
+            let target = self
+                .dir
+                .join(format!("{}.{}-{:03}{}", self.base, stamp, i, SUFFIX));
+            if !target.exists() && !gz_path(&target).exists() {
+                break target;
+            }
+            i += 1;
+        };
+        fs::rename(self.path(), &target)?;
+        self.size = 0;
+        self.day = now.naive_local().date();
+
+        let dir = self.dir.clone();
+        let base = self.base.clone();
+        let retention = self.settings.retention;
+        if self.settings.compress {
+            std::thread::spawn(move || {
+                if let Err(e) = compress(&target) {
+                    error!("Unable to compress log file {:?}: {}", target, e);
+                }
+                if let Err(e) = prune(&dir, &base, retention) {
+                    error!("Unable to prune log files of {}: {}", base, e);
+                }
+            });
+        } else {
+            prune(&dir, &base, retention)?;
+        }
+        Ok(())
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Gzip file and remove the original
+fn compress(path: &Path) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let output = File::create(gz_path(path))?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// Remove rotated files exceeding the retention count, oldest first
+fn prune(dir: &Path, base: &str, retention: usize) -> io::Result<()> {
+    let files = rotated_files(dir, base)?;
+    if files.len() > retention {
+        for file in &files[retention..] {
+            fs::remove_file(dir.join(&file.name))?;
+        }
+    }
+    Ok(())
+}
+
+/// List rotated log files for a file base, newest first
+pub fn rotated_files(dir: &Path, base: &str) -> io::Result<Vec<LogFileInfo>> {
+    let prefix = format!("{}.", base);
+    let current = format!("{}{}", base, SUFFIX);
+    let entries = match fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if name == current
+            || !name.starts_with(&prefix)
+            || !(name.ends_with(SUFFIX) || name.ends_with(SUFFIX_GZ))
+        {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        let modified: DateTime<Utc> = meta.modified()?.into();
+        files.push(LogFileInfo {
+            name,
+            size: meta.len(),
+            modified: modified.timestamp_millis(),
+        });
+    }
+    // timestamps in the name sort chronologically
+    files.sort_unstable_by(|a, b| b.name.cmp(&a.name));
+    Ok(files)
+}
+
+/// Resolve a rotated log file by name, returns None for unknown files
+pub fn rotated_file_path(dir: &Path, base: &str, name: &str) -> io::Result<Option<PathBuf>> {
+    Ok(rotated_files(dir, base)?
+        .into_iter()
+        .find(|f| f.name == name)
+        .map(|f| dir.join(f.name)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn settings(max_size: Option<u64>, retention: usize, compress: bool) -> LogFile {
+        LogFile {
+            max_size,
+            daily: false,
+            retention,
+            compress,
+        }
+    }
+
+    #[test]
+    fn test_file_base() {
+        assert_eq!("3-my_service_1", file_base(3, "my service/1"));
+    }
+
+    #[test]
+    fn test_rotation_retention() {
+        let dir = tempdir().unwrap();
+        let mut writer = LogFileWriter::new(
+            dir.path().to_owned(),
+            1,
+            "test",
+            settings(Some(40), 2, false),
+        );
+        for i in 0..5 {
+            writer
+                .write_line(&ConsoleType::Stdout(format!("line {}", i).into_bytes()))
+                .unwrap();
+        }
+        let files = rotated_files(dir.path(), "1-test").unwrap();
+        assert_eq!(2, files.len());
+        assert!(files[0].name > files[1].name);
+
+        let mut current = String::new();
+        File::open(dir.path().join("1-test.log"))
+            .unwrap()
+            .read_to_string(&mut current)
+            .unwrap();
+        assert!(current.ends_with("[stdout] line 4\n"));
+
+        assert!(rotated_file_path(dir.path(), "1-test", &files[0].name)
+            .unwrap()
+            .is_some());
+        assert!(rotated_file_path(dir.path(), "1-test", "../1-test.log")
+            .unwrap()
+            .is_none());
+        assert!(rotated_file_path(dir.path(), "1-test", "1-test.log")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_compress() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("1-test.20200101-000000-000.log");
+        fs::write(&path, b"some data\n").unwrap();
+        compress(&path).unwrap();
+        assert!(!path.exists());
+        let files = rotated_files(dir.path(), "1-test").unwrap();
+        assert_eq!(1, files.len());
+        assert_eq!("1-test.20200101-000000-000.log.gz", files[0].name);
+    }
+}
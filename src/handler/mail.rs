@@ -0,0 +1,437 @@
+//! Mail notifications on service failures
+use crate::db::models::{ExitDetails, LogAction, NotifyEvents, ServicePerm, SID};
+use crate::db::{DBInterface, DB};
+use crate::messages::unchecked::{ServiceFailure, SetSmtp};
+use crate::settings::{Smtp, SmtpSecurity};
+
+use actix::prelude::*;
+use actix_rt::time::delay_for;
+use actix_threadpool::run as blocking;
+use failure::Fallible;
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+/// Maximum doublings of the retry delay
+const MAX_BACKOFF_EXP: u32 = 6;
+
+#[derive(Default)]
+pub struct MailService {
+    smtp: Option<Arc<Smtp>>,
+    names: HashMap<SID, String>,
+    /// Template of service instances, which share its permissions
+    templates: HashMap<SID, SID>,
+    /// Recent crash times per service for restart loop detection
+    crashes: HashMap<SID, VecDeque<Instant>>,
+}
+
+impl SystemService for MailService {}
+impl Supervised for MailService {}
+
+impl Actor for MailService {
+    type Context = Context<Self>;
+}
+
+impl Handler<SetSmtp> for MailService {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetSmtp, _ctx: &mut Context<Self>) {
+        self.smtp = msg.smtp.map(Arc::new);
+        self.names = msg.names;
+        self.templates = msg.templates;
+    }
+}
+
+impl Handler<ServiceFailure> for MailService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServiceFailure, _ctx: &mut Context<Self>) {
+        let smtp = match self.smtp.as_ref() {
+            Some(v) => v.clone(),
+            None => return,
+        };
+        let mut events = match msg.action {
+            LogAction::ServiceCrashed(_)
+            | LogAction::ServiceLimitExceeded(_)
+            | LogAction::ServiceOomKilled
+            | LogAction::ServiceTimedOut(_) => NotifyEvents::CRASH,
+            LogAction::ServiceStartFailed(_) => NotifyEvents::START_FAILED,
+            _ => return,
+        };
+        if events == NotifyEvents::CRASH && self.crash_loop(&smtp, msg.id, Instant::now()) {
+            events |= NotifyEvents::RESTART_LOOP;
+        }
+        let perm_id = *self.templates.get(&msg.id).unwrap_or(&msg.id);
+        let recipients = match recipients(msg.id, perm_id, events) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Can't read notification subscribers: {}", e);
+                return;
+            }
+        };
+        if recipients.is_empty() {
+            return;
+        }
+        let name = match self.names.get(&msg.id) {
+            Some(v) => v.clone(),
+            None => msg.id.to_string(),
+        };
+        let start = msg.console.len().saturating_sub(smtp.console_lines);
+        let (subject, body) = render(
+            &smtp,
+            &name,
+            &msg.action,
+            msg.exit.as_ref(),
+            events,
+            &msg.console[start..],
+        );
+        for to in recipients {
+            let mail = Mail {
+                to,
+                subject: subject.clone(),
+                body: body.clone(),
+            };
+            let smtp = smtp.clone();
+            actix::spawn(async move {
+                send(smtp, mail, RETRY_BACKOFF).await;
+            });
+        }
+    }
+}
+
+impl MailService {
+    /// Record crash, returns true if it completes a restart loop
+    ///
+    /// Resets the history on a loop, so only every `restart_loop_crashes` crash is reported.
+    fn crash_loop(&mut self, smtp: &Smtp, id: SID, now: Instant) -> bool {
+        let window = Duration::from_secs(smtp.restart_loop_secs);
+        let crashes = self.crashes.entry(id).or_default();
+        while crashes
+            .front()
+            .filter(|t| now.duration_since(**t) > window)
+            .is_some()
+        {
+            crashes.pop_front();
+        }
+        crashes.push_back(now);
+        if crashes.len() >= smtp.restart_loop_crashes {
+            crashes.clear();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Mail addresses of users subscribed to any of the events
+///
+/// Users need the log permission for the service, or admin.
+/// Permissions are read for `perm_id`, the template of instances.
+fn recipients(id: SID, perm_id: SID, events: NotifyEvents) -> Fallible<Vec<String>> {
+    let mut addresses = Vec::new();
+    for (uid, subscribed) in DB.get_notification_subscribers(id)? {
+        if !subscribed.intersects(events) {
+            continue;
+        }
+        let user = DB.get_user(uid)?;
+        if user.admin
+            || DB
+                .get_perm_service(uid, perm_id)?
+                .contains(ServicePerm::LOG)
+        {
+            addresses.push(user.email);
+        }
+    }
+    Ok(addresses)
+}
+
+/// Subject and body of a notification
+fn render(
+    smtp: &Smtp,
+    name: &str,
+    action: &LogAction,
+    exit: Option<&ExitDetails>,
+    events: NotifyEvents,
+    console: &[String],
+) -> (String, String) {
+    let state = match action {
+        LogAction::ServiceCrashed(code) => match exit {
+            Some(ExitDetails {
+                signal: Some(signal),
+                core_dumped,
+                ..
+            }) => format!(
+                "was killed by {}{}",
+                signal_name(*signal),
+                if *core_dumped { " (core dumped)" } else { "" }
+            ),
+            _ => format!("crashed with exit code {}", code),
+        },
+        LogAction::ServiceStartFailed(e) => format!("failed to start: {}", e),
+        LogAction::ServiceLimitExceeded(limit) => format!("crashed: {}", limit),
+        LogAction::ServiceOomKilled => "was killed by the OOM killer".to_owned(),
+        LogAction::ServiceTimedOut(secs) => format!("exceeded its max runtime of {}s", secs),
+        v => format!("{:?}", v),
+    };
+    let subject = if events.contains(NotifyEvents::RESTART_LOOP) {
+        format!("Service {} is in a restart loop", name)
+    } else {
+        format!("Service {} {}", name, state)
+    };
+    let mut body = format!("Service {} {}.\n", name, state);
+    if events.contains(NotifyEvents::RESTART_LOOP) {
+        body.push_str(&format!(
+            "It crashed {} times within {} seconds.\n",
+            smtp.restart_loop_crashes, smtp.restart_loop_secs
+        ));
+    }
+    if !console.is_empty() {
+        body.push_str(&format!("\nLast {} console lines:\n", console.len()));
+        for line in console {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    (subject, body)
+}
+
+fn signal_name(signal: i32) -> String {
+    #[cfg(unix)]
+    {
+        if let Some(name) = crate::handler::signal::name(signal) {
+            return name.to_owned();
+        }
+    }
+    format!("signal {}", signal)
+}
+
+/// Prepared mail for one recipient
+struct Mail {
+    to: String,
+    subject: String,
+    body: String,
+}
+
+fn send_blocking(smtp: &Smtp, mail: &Mail) -> Fallible<()> {
+    let email = EmailBuilder::new()
+        .to(mail.to.as_str())
+        .from(smtp.from.as_str())
+        .subject(mail.subject.as_str())
+        .text(mail.body.as_str())
+        .build()?;
+    let security = match smtp.security {
+        SmtpSecurity::None => ClientSecurity::None,
+        SmtpSecurity::Starttls => ClientSecurity::Required(ClientTlsParameters::new(
+            smtp.host.clone(),
+            TlsConnector::new()?,
+        )),
+        SmtpSecurity::Tls => ClientSecurity::Wrapper(ClientTlsParameters::new(
+            smtp.host.clone(),
+            TlsConnector::new()?,
+        )),
+    };
+    let mut client = SmtpClient::new((smtp.host.as_str(), smtp.port), security)?
+        .timeout(Some(Duration::from_secs(smtp.timeout_secs)));
+    if let (Some(user), Some(password)) = (smtp.username.as_ref(), smtp.password.as_ref()) {
+        client = client.credentials(Credentials::new(user.clone(), password.clone()));
+    }
+    client.transport().send(email.into())?;
+    Ok(())
+}
+
+/// Send with retries, returns true on success
+async fn send(smtp: Arc<Smtp>, mail: Mail, backoff: Duration) -> bool {
+    let mail = Arc::new(mail);
+    let mut attempt = 0;
+    loop {
+        let (config, data) = (smtp.clone(), mail.clone());
+        match blocking(move || send_blocking(&config, &data)).await {
+            Ok(()) => return true,
+            Err(e) => warn!("Sending mail to {} failed: {}", mail.to, e),
+        }
+        if attempt >= smtp.retries {
+            error!(
+                "Sending mail to {} failed after {} attempts, dropping it",
+                mail.to,
+                attempt + 1
+            );
+            return false;
+        }
+        delay_for(backoff * 2u32.pow(attempt.min(MAX_BACKOFF_EXP))).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn smtp(port: u16) -> Smtp {
+        Smtp {
+            host: "127.0.0.1".to_owned(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "sd@example.com".to_owned(),
+            retries: 1,
+            timeout_secs: 5,
+            console_lines: 2,
+            restart_loop_crashes: 3,
+            restart_loop_secs: 60,
+        }
+    }
+
+    /// Serve one SMTP session, returns the DATA content if accepted
+    fn session(stream: TcpStream, accept: bool) -> Option<String> {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        if !accept {
+            writer.write_all(b"421 busy\r\n").unwrap();
+            return None;
+        }
+        writer.write_all(b"220 localhost ready\r\n").unwrap();
+        let mut data = None;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            let command = line.to_uppercase();
+            if command.starts_with("DATA") {
+                writer.write_all(b"354 go ahead\r\n").unwrap();
+                let mut content = String::new();
+                loop {
+                    let mut data_line = String::new();
+                    reader.read_line(&mut data_line).unwrap();
+                    if data_line == ".\r\n" {
+                        break;
+                    }
+                    content.push_str(&data_line);
+                }
+                data = Some(content);
+                writer.write_all(b"250 queued\r\n").unwrap();
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                writer.write_all(b"250 ok\r\n").unwrap();
+            }
+            line.clear();
+        }
+        data
+    }
+
+    /// SMTP stand-in rejecting `reject` connections first
+    fn stand_in(reject: usize) -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for i in 0..=reject {
+                let (stream, _) = listener.accept().unwrap();
+                if let Some(data) = session(stream, i == reject) {
+                    tx.send(data).unwrap();
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    #[test]
+    fn test_render() {
+        let smtp = smtp(25);
+        let console = vec!["at Main.run".to_owned(), "Exception".to_owned()];
+        let (subject, body) = render(
+            &smtp,
+            "foo",
+            &LogAction::ServiceCrashed(3),
+            None,
+            NotifyEvents::CRASH,
+            &console,
+        );
+        assert_eq!("Service foo crashed with exit code 3", subject);
+        assert_eq!(
+            "Service foo crashed with exit code 3.\n\nLast 2 console lines:\nat Main.run\nException\n",
+            body
+        );
+        let (subject, body) = render(
+            &smtp,
+            "foo",
+            &LogAction::ServiceCrashed(1),
+            None,
+            NotifyEvents::CRASH | NotifyEvents::RESTART_LOOP,
+            &[],
+        );
+        assert_eq!("Service foo is in a restart loop", subject);
+        assert!(body.contains("It crashed 3 times within 60 seconds."));
+        let mut exit = ExitDetails {
+            signal: Some(11),
+            core_dumped: true,
+            runtime_secs: 5,
+            restarts: 0,
+        };
+        let (subject, _) = render(
+            &smtp,
+            "foo",
+            &LogAction::ServiceCrashed(-1),
+            Some(&exit),
+            NotifyEvents::CRASH,
+            &[],
+        );
+        assert_eq!("Service foo was killed by SIGSEGV (core dumped)", subject);
+        exit.signal = Some(100);
+        exit.core_dumped = false;
+        let (subject, _) = render(
+            &smtp,
+            "foo",
+            &LogAction::ServiceCrashed(-1),
+            Some(&exit),
+            NotifyEvents::CRASH,
+            &[],
+        );
+        assert_eq!("Service foo was killed by signal 100", subject);
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let smtp = smtp(25);
+        let mut service = MailService::default();
+        let start = Instant::now();
+        assert!(!service.crash_loop(&smtp, 1, start));
+        // outside of window
+        assert!(!service.crash_loop(&smtp, 1, start + Duration::from_secs(61)));
+        assert!(!service.crash_loop(&smtp, 2, start + Duration::from_secs(62)));
+        assert!(!service.crash_loop(&smtp, 1, start + Duration::from_secs(62)));
+        assert!(service.crash_loop(&smtp, 1, start + Duration::from_secs(63)));
+        // history reset
+        assert!(!service.crash_loop(&smtp, 1, start + Duration::from_secs(64)));
+    }
+
+    #[test]
+    fn test_send_retry() {
+        let (port, rx) = stand_in(1);
+        let mail = Mail {
+            to: "oncall@example.com".to_owned(),
+            subject: "Service foo crashed".to_owned(),
+            body: "Last console lines".to_owned(),
+        };
+        let sent = System::new("test").block_on(send(
+            Arc::new(smtp(port)),
+            mail,
+            Duration::from_millis(10),
+        ));
+        assert!(sent);
+        let data = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(data.contains("To: <oncall@example.com>"));
+        assert!(data.contains("Subject: Service foo crashed"));
+        assert!(data.contains("Last console lines"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Restart of services staying above a memory threshold
+use crate::settings::TimeWindow;
+use chrono::NaiveTime;
+use std::time::{Duration, Instant};
+
+/// Memory threshold of a service
+pub struct MemoryLimit {
+    pub bytes: u64,
+    /// Time the usage has to stay above the threshold
+    pub grace: Duration,
+    /// Daily window restarts are allowed in
+    pub window: Option<TimeWindow>,
+}
+
+/// Tracks how long a run stays above its threshold
+#[derive(Default)]
+pub struct MemoryGuard {
+    /// First sample above the threshold
+    over_since: Option<Instant>,
+}
+
+impl MemoryGuard {
+    /// Check a usage sample, returns true if a restart is due
+    ///
+    /// `time` is the local time of day, for the restart window.
+    pub fn check(
+        &mut self,
+        limit: &MemoryLimit,
+        memory: u64,
+        now: Instant,
+        time: NaiveTime,
+    ) -> bool {
+        if memory <= limit.bytes {
+            self.over_since = None;
+            return false;
+        }
+        let since = *self.over_since.get_or_insert(now);
+        let in_window = match limit.window {
+            Some(window) => window.contains(time),
+            None => true,
+        };
+        now.duration_since(since) >= limit.grace && in_window
+    }
+
+    /// Forget previous samples, for a new run
+    pub fn reset(&mut self) {
+        self.over_since = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut limit = MemoryLimit {
+            bytes: 1000,
+            grace: Duration::from_secs(60),
+            window: None,
+        };
+        let mut guard = MemoryGuard::default();
+        let start = Instant::now();
+        let noon = NaiveTime::from_hms(12, 0, 0);
+        assert!(!guard.check(&limit, 2000, start, noon));
+        assert!(!guard.check(&limit, 2000, start + Duration::from_secs(30), noon));
+        assert!(guard.check(&limit, 2000, start + Duration::from_secs(60), noon));
+        // dropping below restarts the grace period
+        assert!(!guard.check(&limit, 500, start + Duration::from_secs(70), noon));
+        assert!(!guard.check(&limit, 2000, start + Duration::from_secs(80), noon));
+        assert!(guard.check(&limit, 2000, start + Duration::from_secs(140), noon));
+
+        limit.window = Some(TimeWindow {
+            start: NaiveTime::from_hms(3, 0, 0),
+            end: NaiveTime::from_hms(5, 0, 0),
+        });
+        let night = NaiveTime::from_hms(4, 0, 0);
+        assert!(!guard.check(&limit, 2000, start + Duration::from_secs(200), noon));
+        assert!(guard.check(&limit, 2000, start + Duration::from_secs(210), night));
+        guard.reset();
+        assert!(!guard.check(&limit, 2000, start + Duration::from_secs(220), night));
+    }
+}
@@ -1,5 +1,7 @@
 use super::error::*;
-use crate::db::models::{ConsoleOutput, LogEntryResolved, LogID, ServicePerm};
+use crate::db::models::{ConsoleOutput, ExitDetails, LogEntryResolved, LogID, ServicePerm};
+use crate::handler::cgroup::CgroupUsage;
+use crate::handler::procstat::UsageSample;
 use crate::handler::service::State;
 use crate::settings::Service;
 use crate::web::models::*;
@@ -12,6 +14,40 @@ pub struct ServiceState {
     pub name: String,
     pub state: State,
     pub uptime: u64,
+    /// Output lines dropped by the rate limit
+    pub dropped_lines: u64,
+    /// Latest resource usage sample while running
+    pub usage: Option<UsageSample>,
+    /// Usage counters of the service cgroup
+    pub cgroup: Option<CgroupUsage>,
+}
+
+/// Service counters for metrics
+pub struct ServiceMetrics {
+    pub id: SID,
+    pub name: String,
+    pub state: State,
+    pub uptime: u64,
+    /// Automatic restarts since daemon start
+    pub restarts: u64,
+    /// Crashes since daemon start
+    pub crashes: u64,
+    pub last_exit_code: i32,
+    /// Console lines captured since daemon start
+    pub console_lines: u64,
+    /// Console bytes captured since daemon start
+    pub console_bytes: u64,
+    /// Output lines dropped by the rate limit
+    pub dropped_lines: u64,
+}
+
+/// Daemon wide metrics
+pub struct DaemonMetrics {
+    pub services: Vec<ServiceMetrics>,
+    /// Stored session logins, including expired ones not yet cleaned up
+    pub logins: usize,
+    /// DB size on disk in bytes
+    pub db_size: u64,
 }
 
 /// Minimal service representation
@@ -147,7 +183,13 @@ pub enum EditUserData {
 /// Unchecked commands, part of the internal API and should not be callable without authentification checks.
 pub mod unchecked {
     use super::*;
+    use crate::db::models::{Date, LogAction, NotifyEvents};
+    use crate::handler::hook::HookStage;
+    use crate::handler::logfile::LogFileInfo;
+    use crate::handler::stdin::StdinMsg;
+    use crate::settings::{Monitoring, Smtp, Webhook};
     use std::collections::HashMap;
+    use std::path::PathBuf;
 
     /// **Unchecked!** Set permissions of user for service  
     /// For administration
@@ -173,7 +215,9 @@ pub mod unchecked {
     #[rtype(result = "Result<(), ControllerError>")]
     pub struct SendStdin {
         pub id: SID,
-        pub input: String,
+        pub input: StdinMsg,
+        /// Don't store the input in logs and console
+        pub sensitive: bool,
         /// Invoker to use for logging
         pub user: Option<UID>,
     }
@@ -186,6 +230,71 @@ pub mod unchecked {
         pub cost: u32,
         pub max_session_age_secs: u32,
         pub disable_totp: bool,
+        /// Template of service instances, for permissions
+        pub templates: HashMap<SID, SID>,
+    }
+
+    /// **Unchecked!** internal, set webhooks  
+    /// For startup
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct SetWebhooks {
+        pub hooks: Vec<Webhook>,
+        /// Service names for payloads
+        pub names: HashMap<SID, String>,
+    }
+
+    /// **Unchecked!** internal, set mail server  
+    /// For startup
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct SetSmtp {
+        pub smtp: Option<Smtp>,
+        /// Service names for mails
+        pub names: HashMap<SID, String>,
+        /// Template of service instances, for permissions
+        pub templates: HashMap<SID, SID>,
+    }
+
+    /// **Unchecked!** internal, service crash or start failure for mail notifications  
+    /// For service internal use.
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct ServiceFailure {
+        pub id: SID,
+        pub action: LogAction,
+        /// Process exit, if any
+        pub exit: Option<ExitDetails>,
+        /// Console snapshot, if taken
+        pub console: Vec<String>,
+    }
+
+    /// **Unchecked!** get mail notification subscription of user for service
+    #[derive(Message)]
+    #[rtype(result = "Result<NotifyEvents, UserError>")]
+    pub struct GetNotifications {
+        pub user: UID,
+        pub service: SID,
+    }
+
+    /// **Unchecked!** set mail notification subscription of user for service
+    #[derive(Message)]
+    #[rtype(result = "Result<(), UserError>")]
+    pub struct SetNotifications {
+        pub user: UID,
+        pub service: SID,
+        pub events: NotifyEvents,
+    }
+
+    /// **Unchecked!** internal, logged service event for webhooks  
+    /// For service internal use.
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct ServiceEvent {
+        pub id: SID,
+        pub time: Date,
+        pub action: LogAction,
+        pub invoker: Option<UID>,
     }
 
     /// **Unchecked!** start service
@@ -206,6 +315,51 @@ pub mod unchecked {
         pub user: Option<UID>,
     }
 
+    /// **Unchecked!** restart service, starts it if not running
+    #[derive(Message)]
+    #[rtype(result = "Result<(), ControllerError>")]
+    pub struct RestartService {
+        pub id: SID,
+        /// Invoker to use for logging
+        pub user: Option<UID>,
+    }
+
+    /// **Unchecked!** pause service
+    #[derive(Message)]
+    #[rtype(result = "Result<(), ControllerError>")]
+    pub struct PauseService {
+        pub id: SID,
+        /// Invoker to use for logging
+        pub user: Option<UID>,
+    }
+
+    /// **Unchecked!** resume paused service
+    #[derive(Message)]
+    #[rtype(result = "Result<(), ControllerError>")]
+    pub struct ResumeService {
+        pub id: SID,
+        /// Invoker to use for logging
+        pub user: Option<UID>,
+    }
+
+    /// **Unchecked!** send allowed signal to service
+    #[derive(Message)]
+    #[rtype(result = "Result<(), ControllerError>")]
+    pub struct SendSignal {
+        pub id: SID,
+        /// Signal name
+        pub signal: String,
+        /// Invoker to use for logging
+        pub user: Option<UID>,
+    }
+
+    /// **Unchecked!** get allowed signals of service
+    #[derive(Message)]
+    #[rtype(result = "Result<Vec<String>, ControllerError>")]
+    pub struct GetSignals {
+        pub id: SID,
+    }
+
     /// **Unchecked!** kill service
     #[derive(Message)]
     #[rtype(result = "Result<(), ControllerError>")]
@@ -222,6 +376,26 @@ pub mod unchecked {
         pub id: SID,
     }
 
+    /// **Unchecked!** set resource usage sampling  
+    /// For startup
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct SetMonitoring {
+        pub monitoring: Monitoring,
+    }
+
+    /// **Unchecked!** get resource usage history of service
+    #[derive(Message)]
+    #[rtype(result = "Result<Vec<UsageSample>, ControllerError>")]
+    pub struct GetUsageHistory {
+        pub id: SID,
+    }
+
+    /// **Unchecked!** get metrics of all services and the daemon
+    #[derive(Message)]
+    #[rtype(result = "Result<DaemonMetrics, ControllerError>")]
+    pub struct GetMetrics {}
+
     /// **Unchecked!** get service latest log
     #[derive(Message)]
     #[rtype(result = "Result<Vec<LogEntryResolved>, ControllerError>")]
@@ -246,6 +420,21 @@ pub mod unchecked {
         pub log_id: LogID,
     }
 
+    /// **Unchecked!** get rotated console log files of service
+    #[derive(Message)]
+    #[rtype(result = "Result<Vec<LogFileInfo>, ControllerError>")]
+    pub struct GetLogFiles {
+        pub id: SID,
+    }
+
+    /// **Unchecked!** get path of rotated console log file
+    #[derive(Message)]
+    #[rtype(result = "Result<PathBuf, ControllerError>")]
+    pub struct GetLogFile {
+        pub id: SID,
+        pub file: String,
+    }
+
     /// **Unchecked!** get service output
     #[derive(Message)]
     #[rtype(result = "Result<ConsoleOutput, ControllerError>")]
@@ -262,6 +451,29 @@ pub mod unchecked {
         pub running: bool,
     }
 
+    /// **Unchecked!** internal, output trigger matched  
+    /// For service internal use.
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct TriggerFired {
+        pub id: SID,
+        /// Index into the service triggers
+        pub trigger: usize,
+        pub line: String,
+    }
+
+    /// **Unchecked!** internal, hook command finished  
+    /// For service internal use.
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct HookFinished {
+        pub id: SID,
+        /// Run the hook belongs to
+        pub run: u64,
+        pub stage: HookStage,
+        pub result: Result<(), String>,
+    }
+
     /// **Unchecked!** internal, startup check  
     /// For startup
     #[derive(Message)]
@@ -274,6 +486,8 @@ pub mod unchecked {
     #[rtype(result = "()")]
     pub struct LoadServices {
         pub data: Vec<Service>,
+        /// Parent of the service cgroups
+        pub cgroup_root: Option<PathBuf>,
     }
 
     /// **Unchecked!** get all service SIDs  
@@ -1,4 +1,22 @@
+pub mod ansi;
+pub mod cgroup;
 pub mod error;
+pub mod hook;
+pub mod logfile;
+pub mod mail;
+pub mod memlimit;
 pub mod messages;
+pub mod procstat;
+#[cfg(unix)]
+pub mod pty;
+pub mod ratelimit;
+#[cfg(unix)]
+pub mod rlimit;
+pub mod search;
 pub mod service;
+#[cfg(unix)]
+pub mod signal;
+pub mod stdin;
 pub mod user;
+pub mod watchdog;
+pub mod webhook;
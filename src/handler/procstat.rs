@@ -0,0 +1,309 @@
+//! Resource usage sampling of service process trees via /proc
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::Instant;
+
+/// Per process values of /proc/<pid>/stat
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcStat {
+    pub ppid: u32,
+    /// utime + stime in clock ticks
+    pub cpu_ticks: u64,
+    pub threads: u64,
+    /// Resident set size in pages
+    pub rss_pages: u64,
+}
+
+/// Snapshot of all processes, by pid
+pub type ProcessTable = HashMap<u32, ProcStat>;
+
+/// Summed usage of a process tree
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TreeUsage {
+    pub processes: u64,
+    pub cpu_ticks: u64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub fds: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+/// Usage sample of a service
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSample {
+    /// Unix timestamp in seconds
+    pub time: u64,
+    /// CPU usage since the previous sample, 100 per fully used core
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+    pub processes: u64,
+    pub threads: u64,
+    pub open_fds: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+}
+
+/// Parse the content of /proc/<pid>/stat
+///
+/// The command name may contain spaces and parentheses, so fields are
+/// counted from its closing parenthesis.
+pub fn parse_stat(content: &str) -> Option<ProcStat> {
+    let rest = &content[content.rfind(')')? + 1..];
+    // fields after comm, starting at field 3 (state)
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(ProcStat {
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)?,
+        threads: field(20)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// Parse read_bytes and write_bytes of /proc/<pid>/io
+pub fn parse_io(content: &str) -> (u64, u64) {
+    let mut read = 0;
+    let mut write = 0;
+    for line in content.lines() {
+        let mut parts = line.split(':');
+        let target = match parts.next() {
+            Some("read_bytes") => &mut read,
+            Some("write_bytes") => &mut write,
+            _ => continue,
+        };
+        if let Some(v) = parts.next().and_then(|v| v.trim().parse().ok()) {
+            *target = v;
+        }
+    }
+    (read, write)
+}
+
+/// Root pid and all its descendants in the table
+pub fn descendants(table: &ProcessTable, root: u32) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (pid, stat) in table.iter() {
+        children.entry(stat.ppid).or_default().push(*pid);
+    }
+    let mut tree = Vec::new();
+    if !table.contains_key(&root) {
+        return tree;
+    }
+    let mut pending = vec![root];
+    while let Some(pid) = pending.pop() {
+        tree.push(pid);
+        if let Some(v) = children.get(&pid) {
+            pending.extend(v.iter().filter(|c| **c != pid));
+        }
+    }
+    tree
+}
+
+/// Read stat of all processes
+#[cfg(target_os = "linux")]
+pub fn process_table() -> io::Result<ProcessTable> {
+    let mut table = ProcessTable::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid: u32 = match entry.file_name().to_str().and_then(|v| v.parse().ok()) {
+            Some(v) => v,
+            None => continue,
+        };
+        // processes can vanish while reading
+        if let Some(stat) = std::fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|v| parse_stat(&v))
+        {
+            table.insert(pid, stat);
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_table() -> io::Result<ProcessTable> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "process sampling is only supported on linux",
+    ))
+}
+
+/// Sum usage of the process tree starting at root, None if root is gone
+pub fn tree_usage(table: &ProcessTable, root: u32) -> Option<TreeUsage> {
+    let tree = descendants(table, root);
+    if tree.is_empty() {
+        return None;
+    }
+    let page_size = page_size();
+    let mut usage = TreeUsage::default();
+    for pid in tree {
+        let stat = &table[&pid];
+        usage.processes += 1;
+        usage.cpu_ticks += stat.cpu_ticks;
+        usage.threads += stat.threads;
+        usage.rss_bytes += stat.rss_pages * page_size;
+        let dir = format!("/proc/{}", pid);
+        if let Ok(fds) = std::fs::read_dir(format!("{}/fd", dir)) {
+            usage.fds += fds.count() as u64;
+        }
+        if let Ok(io) = std::fs::read_to_string(format!("{}/io", dir)) {
+            let (read, write) = parse_io(&io);
+            usage.read_bytes += read;
+            usage.write_bytes += write;
+        }
+    }
+    Some(usage)
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn page_size() -> u64 {
+    4096
+}
+
+#[cfg(target_os = "linux")]
+fn clock_ticks() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) as u64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clock_ticks() -> u64 {
+    100
+}
+
+/// Sample history of a service
+#[derive(Default)]
+pub struct UsageHistory {
+    samples: VecDeque<UsageSample>,
+    /// CPU ticks and time of the previous sample
+    last: Option<(u64, Instant)>,
+}
+
+impl UsageHistory {
+    /// Add a sample, keeping at most `capacity` samples
+    pub fn record(&mut self, usage: TreeUsage, now: Instant, time: u64, capacity: usize) {
+        let cpu_percent = match self.last {
+            Some((ticks, last)) if now > last => {
+                let elapsed = now.duration_since(last).as_secs_f64();
+                let used = usage.cpu_ticks.saturating_sub(ticks) as f64 / clock_ticks() as f64;
+                (used / elapsed * 100.0) as f32
+            }
+            _ => 0.0,
+        };
+        self.last = Some((usage.cpu_ticks, now));
+        while self.samples.len() >= capacity.max(1) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(UsageSample {
+            time,
+            cpu_percent,
+            memory_bytes: usage.rss_bytes,
+            processes: usage.processes,
+            threads: usage.threads,
+            open_fds: usage.fds,
+            io_read_bytes: usage.read_bytes,
+            io_write_bytes: usage.write_bytes,
+        });
+    }
+
+    /// Forget the previous CPU reading, for a new process
+    pub fn restart(&mut self) {
+        self.last = None;
+    }
+
+    pub fn latest(&self) -> Option<&UsageSample> {
+        self.samples.back()
+    }
+
+    pub fn samples(&self) -> Vec<UsageSample> {
+        self.samples.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn stat(ppid: u32) -> ProcStat {
+        ProcStat {
+            ppid,
+            cpu_ticks: 10,
+            threads: 2,
+            rss_pages: 100,
+        }
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let content = "1234 (my (weird) app) S 1 1234 1234 0 -1 4194560 1000 0 0 0 \
+                       150 50 0 0 20 0 7 0 5000 1000000 2500 18446744073709551615";
+        assert_eq!(
+            Some(ProcStat {
+                ppid: 1,
+                cpu_ticks: 200,
+                threads: 7,
+                rss_pages: 2500,
+            }),
+            parse_stat(content)
+        );
+        assert_eq!(None, parse_stat("1234 (truncated) S 1"));
+    }
+
+    #[test]
+    fn test_parse_io() {
+        let content = "rchar: 100\nwchar: 50\nsyscr: 3\nsyscw: 2\n\
+                       read_bytes: 4096\nwrite_bytes: 8192\ncancelled_write_bytes: 0\n";
+        assert_eq!((4096, 8192), parse_io(content));
+    }
+
+    #[test]
+    fn test_descendants() {
+        let mut table = ProcessTable::new();
+        table.insert(1, stat(0));
+        table.insert(10, stat(1));
+        table.insert(11, stat(10));
+        table.insert(12, stat(11));
+        table.insert(20, stat(1));
+        let mut tree = descendants(&table, 10);
+        tree.sort();
+        assert_eq!(vec![10, 11, 12], tree);
+        assert!(descendants(&table, 99).is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = UsageHistory::default();
+        let start = Instant::now();
+        let usage = |ticks| TreeUsage {
+            cpu_ticks: ticks,
+            ..Default::default()
+        };
+        history.record(usage(0), start, 0, 2);
+        assert_eq!(0.0, history.latest().unwrap().cpu_percent);
+        // one second of cpu time within two seconds
+        let ticks = clock_ticks();
+        history.record(usage(ticks), start + Duration::from_secs(2), 2, 2);
+        assert_eq!(50.0, history.latest().unwrap().cpu_percent);
+        history.record(usage(ticks), start + Duration::from_secs(4), 4, 2);
+        let samples = history.samples();
+        assert_eq!(2, samples.len());
+        assert_eq!(2, samples[0].time);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_own_process() {
+        let table = process_table().unwrap();
+        let usage = tree_usage(&table, std::process::id()).unwrap();
+        assert!(usage.processes >= 1);
+        assert!(usage.threads >= 1);
+        assert!(usage.rss_bytes > 0);
+        assert!(usage.fds > 0);
+    }
+}
@@ -0,0 +1,118 @@
+//! Pseudo terminal support for interactive services
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process::Stdio;
+use std::ptr;
+
+/// Master and slave side of a pseudo terminal
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+/// Set FD_CLOEXEC, so the fd isn't leaked into spawned processes
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Pty {
+    /// Open a new pty with the specified window size
+    pub fn open(cols: u16, rows: u16) -> io::Result<Self> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &size,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // take ownership first, closing both on error
+        let pty = unsafe {
+            Self {
+                master: File::from_raw_fd(master),
+                slave: File::from_raw_fd(slave),
+            }
+        };
+        set_cloexec(master)?;
+        set_cloexec(slave)?;
+        // input is already part of the console buffer, don't echo it back
+        unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(slave, &mut termios) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.c_lflag &= !libc::ECHO;
+            if libc::tcsetattr(slave, libc::TCSANOW, &termios) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(pty)
+    }
+
+    /// Stdio handle for the child process, attached to the slave side
+    pub fn stdio(&self) -> io::Result<Stdio> {
+        Ok(Stdio::from(self.slave.try_clone()?))
+    }
+
+    /// Master side, used to read output and write input
+    pub fn master(&self) -> io::Result<File> {
+        self.master.try_clone()
+    }
+}
+
+/// Make stdin the controlling terminal of a new session
+///
+/// To be called in the child before exec.
+pub fn setup_child() -> io::Result<()> {
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the error signals a closed slave side
+///
+/// Linux returns EIO on reads once all slave fds are closed.
+pub fn is_closed(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EIO)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_pty_size() {
+        let pty = Pty::open(120, 40).unwrap();
+        assert_eq!(1, unsafe { libc::isatty(pty.slave.as_raw_fd()) });
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        let ret = unsafe { libc::ioctl(pty.master.as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+        assert_eq!(0, ret);
+        assert_eq!(120, size.ws_col);
+        assert_eq!(40, size.ws_row);
+    }
+}
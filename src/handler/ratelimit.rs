@@ -0,0 +1,97 @@
+//! Output rate limiting per service
+use crate::settings::OutputLimit;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Fixed window limiter for lines and bytes per second
+pub struct RateLimiter {
+    limit: OutputLimit,
+    window: Instant,
+    lines: u32,
+    bytes: u64,
+    dropped: u64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: OutputLimit) -> Self {
+        Self {
+            limit,
+            window: Instant::now(),
+            lines: 0,
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Check a line of `len` bytes against the limit
+    ///
+    /// Returns whether the line is admitted and the amount of lines
+    /// suppressed in the previous window, once that window is over.
+    pub fn admit(&mut self, len: usize, now: Instant) -> (bool, Option<u64>) {
+        let mut suppressed = None;
+        if now.duration_since(self.window) >= WINDOW {
+            suppressed = self.take_dropped();
+            self.window = now;
+            self.lines = 0;
+            self.bytes = 0;
+        }
+        let lines = self.lines + 1;
+        let bytes = self.bytes + len as u64;
+        let exceeded = self.limit.lines.filter(|max| lines > *max).is_some()
+            || self.limit.bytes.filter(|max| bytes > *max).is_some();
+        if exceeded {
+            self.dropped += 1;
+        } else {
+            self.lines = lines;
+            self.bytes = bytes;
+        }
+        (!exceeded, suppressed)
+    }
+
+    /// Suppressed lines of the current window, resets the count
+    pub fn take_dropped(&mut self) -> Option<u64> {
+        match self.dropped {
+            0 => None,
+            v => {
+                self.dropped = 0;
+                Some(v)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let mut limiter = RateLimiter::new(OutputLimit {
+            lines: Some(2),
+            bytes: None,
+        });
+        let start = Instant::now();
+        assert_eq!((true, None), limiter.admit(10, start));
+        assert_eq!((true, None), limiter.admit(10, start));
+        assert_eq!((false, None), limiter.admit(10, start));
+        assert_eq!((false, None), limiter.admit(10, start));
+        assert_eq!((true, Some(2)), limiter.admit(10, start + WINDOW));
+        assert_eq!(None, limiter.take_dropped());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut limiter = RateLimiter::new(OutputLimit {
+            lines: None,
+            bytes: Some(100),
+        });
+        let start = Instant::now();
+        assert_eq!((true, None), limiter.admit(60, start));
+        assert_eq!((false, None), limiter.admit(60, start));
+        // smaller line still fits
+        assert_eq!((true, None), limiter.admit(40, start));
+        assert_eq!(Some(1), limiter.take_dropped());
+        assert_eq!((true, None), limiter.admit(100, start + WINDOW));
+    }
+}
@@ -0,0 +1,71 @@
+//! Resource limits of service processes
+use crate::settings::Rlimits;
+use std::io;
+
+/// Seconds between the soft and hard CPU limit, so SIGXCPU is sent before SIGKILL
+const CPU_GRACE_SECS: u64 = 5;
+
+/// Apply limits to the current process
+///
+/// Runs in the child between fork and exec, so it must not allocate.
+pub fn apply(limits: &Rlimits) -> io::Result<()> {
+    let cpu_hard = limits.cpu_secs.map(|v| v.saturating_add(CPU_GRACE_SECS));
+    let resources = [
+        (libc::RLIMIT_NOFILE, limits.nofile, limits.nofile),
+        (libc::RLIMIT_NPROC, limits.nproc, limits.nproc),
+        (libc::RLIMIT_AS, limits.as_bytes, limits.as_bytes),
+        (libc::RLIMIT_DATA, limits.data_bytes, limits.data_bytes),
+        (libc::RLIMIT_CPU, limits.cpu_secs, cpu_hard),
+        (libc::RLIMIT_CORE, limits.core_bytes, limits.core_bytes),
+    ];
+    for (resource, soft, hard) in resources.iter() {
+        if let (Some(soft), Some(hard)) = (soft, hard) {
+            let limit = libc::rlimit {
+                rlim_cur: *soft as libc::rlim_t,
+                rlim_max: *hard as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(*resource, &limit) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Description of the limit a termination signal is caused by
+pub fn exceeded(signal: i32) -> Option<&'static str> {
+    match signal {
+        libc::SIGXCPU => Some("CPU time limit exceeded (SIGXCPU)"),
+        libc::SIGXFSZ => Some("file size limit exceeded (SIGXFSZ)"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_apply() {
+        let limits = Rlimits {
+            nofile: Some(64),
+            core_bytes: Some(0),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("ulimit -n; ulimit -c");
+        unsafe {
+            cmd.pre_exec(move || apply(&limits));
+        }
+        let output = cmd.output().unwrap();
+        assert_eq!("64\n0\n", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn test_exceeded() {
+        assert!(exceeded(libc::SIGXCPU).is_some());
+        assert_eq!(None, exceeded(libc::SIGSEGV));
+    }
+}
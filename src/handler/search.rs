@@ -0,0 +1,220 @@
+//! Search in console output
+use crate::db::models::{ConsoleOutput, ConsoleType};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Maximum amount of matches per page
+const MAX_LIMIT: usize = 500;
+/// Maximum context lines before and after a match
+const MAX_CONTEXT: usize = 50;
+
+fn default_limit() -> usize {
+    50
+}
+
+/// Console stream to search in
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamFilter {
+    Stdout,
+    Stderr,
+    Stdin,
+    State,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Substring or regular expression
+    pub query: String,
+    /// Treat query as regular expression
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Streams to search in, all if empty
+    #[serde(default)]
+    pub streams: Vec<StreamFilter>,
+    /// Lines of context before and after each match
+    #[serde(default)]
+    pub context: usize,
+    /// Matches to skip, for paging
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum matches to return
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMatch {
+    /// Position of the line in the console output
+    pub index: usize,
+    pub line: ConsoleType<String>,
+    pub before: Vec<ConsoleType<String>>,
+    pub after: Vec<ConsoleType<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    /// Total amount of matches, regardless of paging
+    pub total: usize,
+    pub matches: Vec<SearchMatch>,
+}
+
+/// Stream and plain text of a console line
+fn line_text(line: &ConsoleType<String>) -> (StreamFilter, Cow<'_, str>) {
+    match line {
+        ConsoleType::Stdin(v) => (StreamFilter::Stdin, Cow::Borrowed(v)),
+        ConsoleType::Stdout(v) => (StreamFilter::Stdout, Cow::Borrowed(v)),
+        ConsoleType::Stderr(v) => (StreamFilter::Stderr, Cow::Borrowed(v)),
+        ConsoleType::State(v) => (StreamFilter::State, Cow::Borrowed(v)),
+        ConsoleType::StdoutStyled(spans) => (
+            StreamFilter::Stdout,
+            Cow::Owned(spans.iter().map(|s| s.text.as_str()).collect()),
+        ),
+        ConsoleType::StderrStyled(spans) => (
+            StreamFilter::Stderr,
+            Cow::Owned(spans.iter().map(|s| s.text.as_str()).collect()),
+        ),
+    }
+}
+
+fn copy_lines(lines: &[ConsoleType<String>]) -> Vec<ConsoleType<String>> {
+    lines.iter().map(|l| l.map(String::clone)).collect()
+}
+
+impl SearchQuery {
+    fn matcher(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.regex {
+            Cow::Borrowed(self.query.as_str())
+        } else {
+            Cow::Owned(regex::escape(&self.query))
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.case_insensitive)
+            .build()
+    }
+}
+
+/// Search console output, fails on invalid regular expressions
+pub fn search(output: &ConsoleOutput, query: &SearchQuery) -> Result<SearchResult, regex::Error> {
+    let matcher = query.matcher()?;
+    let context = query.context.min(MAX_CONTEXT);
+    let limit = query.limit.min(MAX_LIMIT);
+    let hits: Vec<usize> = output
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| {
+            let (stream, text) = line_text(line);
+            (query.streams.is_empty() || query.streams.contains(&stream)) && matcher.is_match(&text)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let matches = hits
+        .iter()
+        .skip(query.offset)
+        .take(limit)
+        .map(|&index| SearchMatch {
+            index,
+            line: output[index].map(String::clone),
+            before: copy_lines(&output[index.saturating_sub(context)..index]),
+            after: copy_lines(&output[index + 1..(index + 1 + context).min(output.len())]),
+        })
+        .collect();
+    Ok(SearchResult {
+        total: hits.len(),
+        matches,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::models::StyledSpan;
+
+    fn query(query: &str) -> SearchQuery {
+        SearchQuery {
+            query: query.to_owned(),
+            regex: false,
+            case_insensitive: false,
+            streams: Vec::new(),
+            context: 0,
+            offset: 0,
+            limit: default_limit(),
+        }
+    }
+
+    fn output() -> ConsoleOutput {
+        vec![
+            ConsoleType::State("Starting".to_owned()),
+            ConsoleType::Stdout("booting a.b".to_owned()),
+            ConsoleType::Stderr("Exception in thread main".to_owned()),
+            ConsoleType::StderrStyled(vec![
+                StyledSpan {
+                    text: "  at ".to_owned(),
+                    fg: None,
+                    bg: None,
+                    bold: false,
+                },
+                StyledSpan {
+                    text: "Main.run".to_owned(),
+                    fg: None,
+                    bg: None,
+                    bold: true,
+                },
+            ]),
+            ConsoleType::Stdin("exception".to_owned()),
+            ConsoleType::Stdout("done".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn test_substring() {
+        let output = output();
+        let result = search(&output, &query("a.b")).unwrap();
+        assert_eq!(1, result.total);
+        assert_eq!(1, result.matches[0].index);
+        // no regex interpretation
+        assert_eq!(0, search(&output, &query("a.c")).unwrap().total);
+        // styled spans are joined
+        assert_eq!(1, search(&output, &query("at Main")).unwrap().total);
+    }
+
+    #[test]
+    fn test_filter_and_case() {
+        let output = output();
+        let mut q = query("exception");
+        assert_eq!(1, search(&output, &q).unwrap().total);
+        q.case_insensitive = true;
+        assert_eq!(2, search(&output, &q).unwrap().total);
+        q.streams = vec![StreamFilter::Stderr];
+        let result = search(&output, &q).unwrap();
+        assert_eq!(1, result.total);
+        assert_eq!(2, result.matches[0].index);
+    }
+
+    #[test]
+    fn test_regex_context_paging() {
+        let output = output();
+        let mut q = query("^(booting|done|  at)");
+        q.regex = true;
+        q.context = 1;
+        q.offset = 1;
+        q.limit = 1;
+        let result = search(&output, &q).unwrap();
+        assert_eq!(3, result.total);
+        assert_eq!(1, result.matches.len());
+        assert_eq!(3, result.matches[0].index);
+        assert_eq!(1, result.matches[0].before.len());
+        assert_eq!(1, result.matches[0].after.len());
+
+        q.offset = 2;
+        let result = search(&output, &q).unwrap();
+        assert_eq!(5, result.matches[0].index);
+        assert!(result.matches[0].after.is_empty());
+
+        q.query = String::from("(");
+        assert!(search(&output, &q).is_err());
+    }
+}
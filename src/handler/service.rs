@@ -1,27 +1,48 @@
+use super::ansi::StyleParser;
+use super::cgroup::{self, Cgroup, CgroupUsage};
 use super::error::*;
-use crate::db::models::{ConsoleOutput, ConsoleType, LogAction, LogEntryResolved, NewLogEntry};
+use super::hook::{self, HookStage};
+use super::logfile::{self, LogFileInfo, LogFileWriter, LOG_DIR};
+use super::memlimit::{MemoryGuard, MemoryLimit};
+use super::procstat::{self, UsageHistory, UsageSample};
+#[cfg(unix)]
+use super::pty::{self, Pty};
+use super::ratelimit::RateLimiter;
+#[cfg(unix)]
+use super::rlimit;
+#[cfg(unix)]
+use super::signal;
+use super::stdin::{self, QueuedInput, StdinMsg};
+use super::watchdog::OutputWatchdog;
+use crate::db::models::{
+    ConsoleOutput, ConsoleType, ExitDetails, LogAction, LogEntryResolved, NewLogEntry,
+};
 use crate::db::{DBInterface, DB};
+use crate::handler::mail::MailService;
 use crate::handler::user::UserService;
+use crate::handler::webhook::WebhookService;
 use crate::messages::unchecked::*;
 use crate::messages::*;
-use crate::settings::Service;
+use crate::settings::{
+    AnsiMode, OutputLimit, Service, Trigger, TriggerAction, TriggerStream, WatchdogAction,
+};
 use crate::web::models::SID;
 
 use actix::fut::{err, ok, Either};
 use actix::prelude::*;
 use actix::spawn;
 use arraydeque::{ArrayDeque, Wrapping};
+use chrono::Local;
 use failure::Fallible;
-use futures::stream::StreamExt;
 use metrohash::MetroHashMap;
 use serde::Serialize;
 use std::env::current_dir;
 use std::ffi::OsString;
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
 use strip_ansi_escapes as ansi_esc;
-use tokio::io::AsyncBufReadExt;
-use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::process::Command;
 
 use futures_util::future::TryFutureExt;
@@ -31,17 +52,23 @@ use futures::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::process::Stdio;
 use std::sync::atomic::Ordering;
-use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 pub struct ServiceController {
     services: MetroHashMap<SID, LoadedService>,
+    /// Samples to keep per service
+    usage_history: usize,
+    sampler: Option<SpawnHandle>,
 }
 
 impl Default for ServiceController {
     fn default() -> Self {
         Self {
             services: MetroHashMap::default(),
+            usage_history: 0,
+            sampler: None,
         }
     }
 }
@@ -57,13 +84,14 @@ impl SystemService for ServiceController {}
 impl Supervised for ServiceController {}
 
 impl ServiceController {
-    fn load_services(&mut self, data: Vec<Service>) -> Fallible<()> {
+    fn load_services(&mut self, data: Vec<Service>, cgroup_root: Option<PathBuf>) -> Fallible<()> {
         trace!("Loading services");
         if !self.services.is_empty() {
             return Err(ControllerError::ServicesNotEmpty.into());
         }
         let services: Vec<Instance> = data.into_iter().map(|d| d.into()).collect();
-        services.into_iter().for_each(|i| {
+        services.into_iter().for_each(|mut i| {
+            i.cgroup_root = cgroup_root.clone();
             Self::log(
                 NewLogEntry::new(LogAction::SystemStartup, None),
                 i.model.id,
@@ -74,12 +102,251 @@ impl ServiceController {
         trace!("Loaded {} services", self.services.len());
         Ok(())
     }
-    /// Wrapper to log to DB
+    /// Wrapper to log to DB, notifies webhooks and mail subscribers
     pub fn log(entry: NewLogEntry, sid: SID, console_log: Option<ConsoleOutput>) {
+        WebhookService::from_registry().do_send(ServiceEvent {
+            id: sid,
+            time: entry.time,
+            action: entry.action.clone(),
+            invoker: entry.invoker,
+        });
+        match entry.action {
+            LogAction::ServiceCrashed(_)
+            | LogAction::ServiceLimitExceeded(_)
+            | LogAction::ServiceOomKilled
+            | LogAction::ServiceTimedOut(_)
+            | LogAction::ServiceStartFailed(_) => {
+                MailService::from_registry().do_send(ServiceFailure {
+                    id: sid,
+                    action: entry.action.clone(),
+                    exit: entry.exit.clone(),
+                    console: console_log
+                        .iter()
+                        .flatten()
+                        .map(|l| l.text().into_owned())
+                        .collect(),
+                })
+            }
+            _ => (),
+        }
         if let Err(e) = DB.insert_log_entry(sid, entry, console_log) {
             error!("Can't insert DB log entry! {}", e);
         }
     }
+    /// Soft-stop service, kill it if still running after `stop_timeout_secs`
+    ///
+    /// Runs the pre_stop hook first if soft-stopping is possible.
+    fn stop_sequence(&mut self, id: SID, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&id) {
+            Some(v) => v,
+            None => return,
+        };
+        if instance.model.pre_stop.is_some() && instance.can_soft_stop().is_ok() {
+            instance.state.set_state(State::Stopping);
+            instance.start_hook(HookStage::PreStop(true), ctx.address());
+            return;
+        }
+        self.stop_sequence_now(id, ctx);
+    }
+    /// Stop sequence without hook
+    ///
+    /// Kills right away if paused or without soft-stop command.
+    fn stop_sequence_now(&mut self, id: SID, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&id) {
+            Some(v) => v,
+            None => return,
+        };
+        if instance.state.get_state() == State::Paused || instance.soft_stop().is_err() {
+            instance.kill();
+            return;
+        }
+        let run = instance.run_id;
+        let timeout = Duration::from_secs(instance.model.stop_timeout_secs);
+        ctx.run_later(timeout, move |act, _ctx| {
+            if let Some(instance) = act.services.get_mut(&id) {
+                if instance.run_id == run && instance.running.load(Ordering::Acquire) {
+                    instance.tty.push(ConsoleType::State(
+                        b"Stop timeout reached, killing process".to_vec(),
+                    ));
+                    instance.kill();
+                }
+            }
+        });
+    }
+    /// Stop a run exceeding `max_runtime_secs`
+    fn runtime_exceeded(&mut self, id: SID, run: u64, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&id) {
+            Some(v) => v,
+            None => return,
+        };
+        if instance.run_id != run || !instance.running.load(Ordering::Acquire) {
+            return;
+        }
+        instance.timed_out = true;
+        instance.tty.push(ConsoleType::State(
+            format!(
+                "Max runtime of {}s exceeded, stopping",
+                instance.model.max_runtime_secs.unwrap_or(0)
+            )
+            .into_bytes(),
+        ));
+        self.stop_sequence(id, ctx);
+    }
+    /// Run the watchdog action if the service printed nothing for `output_watchdog_secs`
+    ///
+    /// `fired` is the silence the action already ran for.
+    fn check_output(&mut self, id: SID, run: u64, fired: Option<u64>, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&id) {
+            Some(v) => v,
+            None => return,
+        };
+        let limit = match instance.model.output_watchdog_secs {
+            Some(v) => Duration::from_secs(v),
+            None => return,
+        };
+        if instance.run_id != run || !instance.running.load(Ordering::Acquire) {
+            return;
+        }
+        if instance.state.get_state() != State::Running {
+            // silent by intention while paused or stopping, count anew afterwards
+            instance.watchdog.reset(Instant::now());
+            ctx.run_later(limit, move |act, ctx| act.check_output(id, run, fired, ctx));
+            return;
+        }
+        let (last, silence) = instance.watchdog.silence(Instant::now());
+        let mut next = limit;
+        let mut fired = fired;
+        if silence < limit {
+            next = limit - silence;
+        } else if fired != Some(last) {
+            fired = Some(last);
+            let secs = silence.as_secs();
+            instance.tty.push(ConsoleType::State(
+                format!("No output for {}s", secs).into_bytes(),
+            ));
+            match instance.model.output_watchdog_action {
+                WatchdogAction::Log => {
+                    Self::log(
+                        NewLogEntry::new(LogAction::OutputSilent(secs), None),
+                        id,
+                        None,
+                    );
+                }
+                WatchdogAction::Snapshot => {
+                    let snapshot = instance.console_output();
+                    Self::log(
+                        NewLogEntry::new(LogAction::OutputSilent(secs), None),
+                        id,
+                        Some(snapshot),
+                    );
+                }
+                WatchdogAction::Restart => {
+                    Self::log(
+                        NewLogEntry::new(LogAction::OutputSilent(secs), None),
+                        id,
+                        None,
+                    );
+                    instance.restart_pending = true;
+                    self.stop_sequence(id, ctx);
+                    return;
+                }
+                WatchdogAction::Probe => {
+                    instance.probe_output();
+                    let timeout = Duration::from_secs(instance.model.output_watchdog_reply_secs);
+                    ctx.run_later(timeout, move |act, ctx| act.check_probe(id, run, secs, ctx));
+                }
+            }
+        }
+        ctx.run_later(next, move |act, ctx| act.check_output(id, run, fired, ctx));
+    }
+    /// Restart the service if the watchdog probe got no reply
+    fn check_probe(&mut self, id: SID, run: u64, silence: u64, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&id) {
+            Some(v) => v,
+            None => return,
+        };
+        if instance.run_id != run
+            || !instance.running.load(Ordering::Acquire)
+            || instance.state.get_state() != State::Running
+        {
+            return;
+        }
+        if instance.watchdog.finish_probe() {
+            instance
+                .tty
+                .push(ConsoleType::State(b"Watchdog probe answered".to_vec()));
+            return;
+        }
+        instance.tty.push(ConsoleType::State(
+            b"No reply to watchdog probe, restarting".to_vec(),
+        ));
+        let snapshot = instance.console_output();
+        Self::log(
+            NewLogEntry::new(LogAction::OutputSilent(silence), None),
+            id,
+            Some(snapshot),
+        );
+        instance.restart_pending = true;
+        self.stop_sequence(id, ctx);
+    }
+    /// Record resource usage of all running services
+    ///
+    /// Restarts services above their memory threshold.
+    fn sample_usage(&mut self, ctx: &mut Context<Self>) {
+        if self.services.values().all(|v| v.pid.is_none()) {
+            return;
+        }
+        let table = match procstat::process_table() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Can't read process table: {}", e);
+                return;
+            }
+        };
+        let now = Instant::now();
+        let time = get_system_time_64();
+        let time_of_day = Local::now().time();
+        let mut restart = Vec::new();
+        for (id, instance) in self.services.iter_mut() {
+            if let Some(usage) = instance
+                .pid
+                .and_then(|pid| procstat::tree_usage(&table, pid))
+            {
+                let memory = usage.rss_bytes;
+                instance.usage.record(usage, now, time, self.usage_history);
+                if let Some(limit) = instance.memory_limit() {
+                    // pauses and stops in progress aren't overridden
+                    if !instance.restart_pending
+                        && instance.state.get_state() == State::Running
+                        && instance
+                            .memory_guard
+                            .check(&limit, memory, now, time_of_day)
+                    {
+                        restart.push((*id, memory));
+                    }
+                }
+            }
+        }
+        for (id, memory) in restart {
+            let memory_mb = memory / 1024 / 1024;
+            if let Some(instance) = self.services.get_mut(&id) {
+                instance.tty.push(ConsoleType::State(
+                    format!(
+                        "Memory usage of {} MB above threshold, restarting",
+                        memory_mb
+                    )
+                    .into_bytes(),
+                ));
+                instance.restart_pending = true;
+            }
+            Self::log(
+                NewLogEntry::new(LogAction::MemoryRestart(memory_mb), None),
+                id,
+                None,
+            );
+            self.stop_sequence(id, ctx);
+        }
+    }
 }
 
 impl Actor for ServiceController {
@@ -101,7 +368,7 @@ impl Handler<StartService> for ServiceController {
         trace!("Start received: {}", msg.id);
         match self.services.get_mut(&msg.id) {
             Some(instance) => {
-                if instance.running.load(Ordering::SeqCst) {
+                if instance.running.load(Ordering::SeqCst) || instance.starting {
                     return Err(ControllerError::ServiceRunning);
                 }
                 trace!("starting..");
@@ -126,17 +393,44 @@ impl Handler<SendStdin> for ServiceController {
 
     fn handle(&mut self, msg: SendStdin, _ctx: &mut Context<Self>) -> Self::Result {
         if let Some(service) = self.services.get_mut(&msg.id) {
+            let model = &service.model;
+            if !stdin::allowed(
+                model.stdin,
+                &model.stdin_allow,
+                &model.stdin_deny,
+                &msg.input,
+            ) {
+                let input = match &msg.input {
+                    StdinMsg::Data(data) => {
+                        stdin::display(data, msg.sensitive, &model.stdin_mask).1
+                    }
+                    StdinMsg::Close => String::from("EOF"),
+                };
+                Self::log(
+                    NewLogEntry::new(LogAction::StdinDenied(input), msg.user),
+                    msg.id,
+                    None,
+                );
+                return Err(ControllerError::StdinDenied);
+            }
             if !service.running.load(Ordering::Relaxed) {
                 return Err(ControllerError::ServiceStopped);
             }
             if let Some(stdin) = service.stdin.as_mut() {
-                match stdin.try_send(format!("{}\n", msg.input)) {
+                let (display, action) = match &msg.input {
+                    StdinMsg::Data(data) => {
+                        let (console, log) =
+                            stdin::display(data, msg.sensitive, &service.model.stdin_mask);
+                        (console, LogAction::Stdin(log))
+                    }
+                    StdinMsg::Close => (String::new(), LogAction::StdinEof),
+                };
+                match stdin.try_send(QueuedInput {
+                    input: msg.input,
+                    display,
+                }) {
                     Ok(()) => {
-                        Self::log(
-                            NewLogEntry::new(LogAction::Stdin(msg.input), msg.user),
-                            msg.id,
-                            None,
-                        );
+                        Self::log(NewLogEntry::new(action, msg.user), msg.id, None);
                         return Ok(());
                     }
                     Err(e) => {
@@ -157,8 +451,7 @@ impl Handler<KillService> for ServiceController {
 
     fn handle(&mut self, msg: KillService, _ctx: &mut Context<Self>) -> Self::Result {
         if let Some(service) = self.services.get_mut(&msg.id) {
-            if let Some(v) = service.kill_handle.take() {
-                let _ = v.send(());
+            if service.kill() {
                 Self::log(
                     NewLogEntry::new(LogAction::ServiceCmdKilled, msg.user),
                     msg.id,
@@ -173,31 +466,147 @@ impl Handler<KillService> for ServiceController {
     }
 }
 
+impl Handler<PauseService> for ServiceController {
+    type Result = Result<(), ControllerError>;
+
+    fn handle(&mut self, msg: PauseService, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(service) = self.services.get_mut(&msg.id) {
+            match service.state.get_state() {
+                State::Running => (),
+                State::Paused => return Err(ControllerError::ServicePaused),
+                _ => return Err(ControllerError::ServiceStopped),
+            }
+            service
+                .set_paused(true)
+                .map_err(ControllerError::SignalIOError)?;
+            service.state.set_state(State::Paused);
+            service
+                .tty
+                .push(ConsoleType::State(b"Process paused".to_vec()));
+            Self::log(
+                NewLogEntry::new(LogAction::ServicePaused, msg.user),
+                msg.id,
+                None,
+            );
+            Ok(())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<ResumeService> for ServiceController {
+    type Result = Result<(), ControllerError>;
+
+    fn handle(&mut self, msg: ResumeService, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(service) = self.services.get_mut(&msg.id) {
+            if service.state.get_state() != State::Paused {
+                return Err(ControllerError::ServiceNotPaused);
+            }
+            service
+                .set_paused(false)
+                .map_err(ControllerError::SignalIOError)?;
+            service.state.set_state(State::Running);
+            service
+                .tty
+                .push(ConsoleType::State(b"Process resumed".to_vec()));
+            Self::log(
+                NewLogEntry::new(LogAction::ServiceResumed, msg.user),
+                msg.id,
+                None,
+            );
+            Ok(())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<SendSignal> for ServiceController {
+    type Result = Result<(), ControllerError>;
+
+    fn handle(&mut self, msg: SendSignal, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(service) = self.services.get_mut(&msg.id) {
+            if !service.running.load(Ordering::Acquire) {
+                return Err(ControllerError::ServiceStopped);
+            }
+            let name = service.send_signal(&msg.signal)?;
+            service
+                .tty
+                .push(ConsoleType::State(format!("Sent {}", name).into_bytes()));
+            Self::log(
+                NewLogEntry::new(LogAction::Signal(name.to_owned()), msg.user),
+                msg.id,
+                None,
+            );
+            Ok(())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<GetSignals> for ServiceController {
+    type Result = Result<Vec<String>, ControllerError>;
+
+    fn handle(&mut self, msg: GetSignals, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(service) = self.services.get(&msg.id) {
+            Ok(service.model.signals.clone())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<RestartService> for ServiceController {
+    type Result = Result<(), ControllerError>;
+
+    fn handle(&mut self, msg: RestartService, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(service) = self.services.get_mut(&msg.id) {
+            if service.restart_cmd {
+                return Err(ControllerError::RestartPending);
+            }
+            Self::log(
+                NewLogEntry::new(LogAction::ServiceCmdRestart, msg.user),
+                msg.id,
+                None,
+            );
+            if !service.running.load(Ordering::Acquire) {
+                return service
+                    .run(ctx.address())
+                    .map_err(ControllerError::StartupIOError);
+            }
+            service.restart_pending = true;
+            service.restart_cmd = true;
+            self.stop_sequence(msg.id, ctx);
+            Ok(())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
 impl Handler<StopService> for ServiceController {
     type Result = Result<(), ControllerError>;
 
-    fn handle(&mut self, msg: StopService, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: StopService, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(service) = self.services.get_mut(&msg.id) {
             if !service.running.load(Ordering::Acquire) {
                 return Err(ControllerError::ServiceStopped);
             }
-            let stdin = match service.stdin.as_mut() {
-                Some(stdin) => stdin,
-                None => return Err(ControllerError::NoServiceHandle),
-            };
-            let stop_msg = match service.model.soft_stop.as_ref() {
-                Some(stop_msg) => stop_msg,
-                None => return Err(ControllerError::NoSoftStop),
-            };
-            if let Err(e) = stdin.try_send(format!("{}\n", stop_msg)) {
-                warn!("Can't soft-stop process: {}", e);
+            if service.model.pre_stop.is_some() {
+                // soft-stop after the hook
+                service.can_soft_stop()?;
+                service.state.set_state(State::Stopping);
+                service.start_hook(HookStage::PreStop(false), ctx.address());
+            } else {
+                service.soft_stop()?;
             }
             Self::log(
                 NewLogEntry::new(LogAction::ServiceCmdStop, msg.user),
                 msg.id,
                 None,
             );
-            service.state.set_state(State::Stopping);
             Ok(())
         } else {
             Err(ControllerError::InvalidInstance(msg.id))
@@ -209,17 +618,23 @@ impl Handler<ServiceStateChanged> for ServiceController {
     type Result = ();
     fn handle(&mut self, msg: ServiceStateChanged, ctx: &mut Context<Self>) {
         if let Some(instance) = self.services.get_mut(&msg.id) {
-            let state = instance.state.get_state();
+            let mut state = instance.state.get_state();
+            if !msg.running && mem::replace(&mut instance.timed_out, false) {
+                state = State::TimedOut;
+                instance.state.set_state(state);
+            }
             let mut snapshot = false;
             let log_action = match state {
                 State::Ended => {
                     snapshot = instance.model.snapshot_console_on_stop;
                     LogAction::ServiceEnded
                 }
-                State::Running => LogAction::ServiceStarted,
+                // paused right after start
+                State::Running | State::Paused => LogAction::ServiceStarted,
                 State::Crashed => {
                     snapshot = instance.model.snapshot_console_on_crash;
-                    LogAction::ServiceCrashed(instance.crash_code.load(Ordering::Acquire))
+                    instance.crashes += 1;
+                    instance.crash_action()
                 }
                 State::Stopped => {
                     snapshot = instance.model.snapshot_console_on_manual_stop;
@@ -229,6 +644,10 @@ impl Handler<ServiceStateChanged> for ServiceController {
                     snapshot = instance.model.snapshot_console_on_manual_kill;
                     LogAction::ServiceKilled
                 }
+                State::TimedOut => {
+                    snapshot = instance.model.snapshot_console_on_timeout;
+                    LogAction::ServiceTimedOut(instance.model.max_runtime_secs.unwrap_or(0))
+                }
                 State::Stopping => {
                     unreachable!("unreachable: service-stopping-state in state update!")
                 }
@@ -239,18 +658,59 @@ impl Handler<ServiceStateChanged> for ServiceController {
                 false => None,
             };
 
-            Self::log(NewLogEntry::new(log_action, None), msg.id, log_data);
-
+            let mut entry = NewLogEntry::new(log_action, None);
             if !msg.running {
                 instance.end_time = Some(get_system_time_64());
+                entry = entry.exit(instance.exit_details());
+            }
+            Self::log(entry, msg.id, log_data);
 
-                let restart = if instance.model.restart_always && state == State::Ended {
-                    true
-                } else {
-                    instance.model.restart && state == State::Crashed
-                };
+            if msg.running {
+                instance.start_hook(HookStage::PostStart, ctx.address());
+            }
+            if let (true, Some(secs)) = (msg.running, instance.model.max_runtime_secs) {
+                let (id, run) = (msg.id, instance.run_id);
+                ctx.run_later(Duration::from_secs(secs), move |act, ctx| {
+                    act.runtime_exceeded(id, run, ctx)
+                });
+            }
+            if let (true, Some(secs)) = (msg.running, instance.model.output_watchdog_secs) {
+                let (id, run) = (msg.id, instance.run_id);
+                ctx.run_later(Duration::from_secs(secs), move |act, ctx| {
+                    act.check_output(id, run, None, ctx)
+                });
+            }
+
+            if !msg.running {
+                instance.exited();
 
-                if restart {
+                let restart = instance.restart_pending
+                    || match state {
+                        State::Ended | State::Crashed => instance
+                            .model
+                            .restart_on_exit(instance.exit_code(), state == State::Crashed),
+                        State::TimedOut => instance.model.restart,
+                        _ => false,
+                    };
+                instance.restart_pending = false;
+                let restart_cmd = mem::replace(&mut instance.restart_cmd, false);
+
+                if instance.model.post_stop.is_some() {
+                    // restart after the hook
+                    instance.kill_handle = None;
+                    instance.stdin = None;
+                    let restart = match restart {
+                        true => Some(restart_cmd),
+                        false => None,
+                    };
+                    instance.start_hook(HookStage::PostStop(restart), ctx.address());
+                } else if restart && restart_cmd {
+                    // already logged as restart command
+                    if let Err(e) = instance.run(ctx.address()) {
+                        warn!("Restart of {} failed: {}", instance.model.name, e);
+                    }
+                } else if restart {
+                    instance.restarts += 1;
                     ctx.address().do_send(StartService {
                         id: instance.model.id,
                         user: None,
@@ -265,6 +725,134 @@ impl Handler<ServiceStateChanged> for ServiceController {
     }
 }
 
+impl Handler<HookFinished> for ServiceController {
+    type Result = ();
+    fn handle(&mut self, msg: HookFinished, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&msg.id) {
+            Some(v) => v,
+            None => return,
+        };
+        let name = msg.stage.name();
+        let status = match msg.result.as_ref() {
+            Ok(()) => format!("{} hook finished", name),
+            Err(e) => format!("{} hook failed: {}", name, e),
+        };
+        instance.tty.push(ConsoleType::State(status.into_bytes()));
+        // later runs aren't affected by hooks of earlier ones
+        let current = instance.run_id == msg.run;
+        let running = instance.running.load(Ordering::Acquire);
+        match msg.stage {
+            HookStage::PreStart => {
+                instance.starting = false;
+                match msg.result {
+                    // start failures are logged by spawn
+                    Ok(()) => {
+                        let _ = instance.spawn(ctx.address());
+                    }
+                    Err(e) => Self::log(
+                        NewLogEntry::new(
+                            LogAction::ServiceStartFailed(format!("pre_start hook failed: {}", e)),
+                            None,
+                        ),
+                        msg.id,
+                        None,
+                    ),
+                }
+            }
+            HookStage::PostStart => (),
+            HookStage::PreStop(kill_fallback) if current && running => {
+                if kill_fallback {
+                    self.stop_sequence_now(msg.id, ctx);
+                } else if let Err(e) = instance.soft_stop() {
+                    warn!("Can't soft-stop {} after hook: {}", instance.model.name, e);
+                }
+            }
+            HookStage::PostStop(Some(true)) if current && !running => {
+                // already logged as restart command
+                if let Err(e) = instance.run(ctx.address()) {
+                    warn!("Restart of {} failed: {}", instance.model.name, e);
+                }
+            }
+            HookStage::PostStop(Some(false)) if current && !running => {
+                instance.restarts += 1;
+                ctx.address().do_send(StartService {
+                    id: msg.id,
+                    user: None,
+                });
+            }
+            HookStage::PreStop(_) | HookStage::PostStop(_) => (),
+        }
+    }
+}
+
+impl Handler<TriggerFired> for ServiceController {
+    type Result = ();
+    fn handle(&mut self, msg: TriggerFired, ctx: &mut Context<Self>) {
+        let instance = match self.services.get_mut(&msg.id) {
+            Some(v) => v,
+            None => return,
+        };
+        let trigger = match instance.model.triggers.get(msg.trigger) {
+            Some(v) => v,
+            None => return,
+        };
+        instance.tty.push(ConsoleType::State(
+            format!(
+                "Trigger \"{}\" fired, action {:?}",
+                trigger.pattern.0.as_str(),
+                trigger.action
+            )
+            .into_bytes(),
+        ));
+        let (action, snapshot) = match trigger.action {
+            TriggerAction::Notify => (LogAction::OutputNotify(msg.line), None),
+            TriggerAction::Snapshot => (
+                LogAction::OutputTrigger(msg.line),
+                Some(instance.console_output()),
+            ),
+            _ => (LogAction::OutputTrigger(msg.line), None),
+        };
+        Self::log(NewLogEntry::new(action, None), msg.id, snapshot);
+        match trigger.action {
+            TriggerAction::Restart => {
+                // pauses and stops in progress aren't overridden
+                if !instance.restart_pending && instance.state.get_state() == State::Running {
+                    instance.restart_pending = true;
+                    self.stop_sequence(msg.id, ctx);
+                }
+            }
+            TriggerAction::Stop => {
+                if instance.model.soft_stop.is_some() {
+                    ctx.address().do_send(StopService {
+                        id: msg.id,
+                        user: None,
+                    });
+                } else {
+                    ctx.address().do_send(KillService {
+                        id: msg.id,
+                        user: None,
+                    });
+                }
+            }
+            TriggerAction::Stdin => {
+                if let (Some(stdin), Some(input)) =
+                    (instance.stdin.as_mut(), trigger.input.as_ref())
+                {
+                    let display =
+                        stdin::display(input.as_bytes(), false, &instance.model.stdin_mask).0;
+                    if let Err(e) = stdin.try_send(QueuedInput {
+                        input: StdinMsg::line(input),
+                        display,
+                    }) {
+                        warn!("Can't send trigger input: {}", e);
+                    }
+                }
+            }
+            TriggerAction::Log | TriggerAction::Snapshot | TriggerAction::Notify => (),
+        }
+    }
+}
+
 impl Handler<GetOutput> for ServiceController {
     type Result = Result<ConsoleOutput, ControllerError>;
 
@@ -281,24 +869,20 @@ impl Handler<GetUserServicePermsAll> for ServiceController {
     type Result = Result<HashMap<SID, SPMin>, ControllerError>;
 
     fn handle(&mut self, msg: GetUserServicePermsAll, _ctx: &mut Context<Self>) -> Self::Result {
+        let perms = DB.get_all_perm_service(msg.user)?;
         let mut data = HashMap::with_capacity(self.services.len());
         self.services.iter().for_each(|(k, v)| {
+            // instances share the permissions of their template
+            let perm_id = v.model.template.unwrap_or(*k);
             data.insert(
                 k.clone(),
                 SPMin {
                     id: *k,
                     name: v.model.name.clone(),
-                    has_perm: false,
+                    has_perm: perms.get(&perm_id).filter(|p| !p.is_empty()).is_some(),
                 },
             );
         });
-        DB.get_all_perm_service(msg.user)?
-            .iter()
-            .for_each(|(k, v)| {
-                if let Some(mut entry) = data.get_mut(k) {
-                    entry.has_perm = !v.is_empty();
-                }
-            });
         Ok(data)
     }
 }
@@ -341,6 +925,9 @@ impl Handler<GetSessionServices> for ServiceController {
                             name: v.model.name.clone(),
                             state: v.state.get_state(),
                             uptime: v.uptime(),
+                            dropped_lines: v.tty.dropped.load(Ordering::Relaxed),
+                            usage: v.current_usage(),
+                            cgroup: v.cgroup_usage(),
                         })
                     } else {
                         None
@@ -375,6 +962,9 @@ impl Handler<GetServiceState> for ServiceController {
                 name: v.model.name.clone(),
                 state: v.state.get_state(),
                 uptime: v.uptime(),
+                dropped_lines: v.tty.dropped.load(Ordering::Relaxed),
+                usage: v.current_usage(),
+                cgroup: v.cgroup_usage(),
             })
         } else {
             Err(ControllerError::InvalidInstance(msg.id))
@@ -382,6 +972,59 @@ impl Handler<GetServiceState> for ServiceController {
     }
 }
 
+impl Handler<SetMonitoring> for ServiceController {
+    type Result = ();
+    fn handle(&mut self, msg: SetMonitoring, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.sampler.take() {
+            ctx.cancel_future(handle);
+        }
+        self.usage_history = msg.monitoring.history;
+        if msg.monitoring.interval_secs > 0 {
+            let interval = Duration::from_secs(msg.monitoring.interval_secs);
+            self.sampler = Some(ctx.run_interval(interval, |act, ctx| act.sample_usage(ctx)));
+        }
+    }
+}
+
+impl Handler<GetUsageHistory> for ServiceController {
+    type Result = Result<Vec<UsageSample>, ControllerError>;
+    fn handle(&mut self, msg: GetUsageHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(instance) = self.services.get(&msg.id) {
+            Ok(instance.usage.samples())
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<GetMetrics> for ServiceController {
+    type Result = Result<DaemonMetrics, ControllerError>;
+    fn handle(&mut self, _msg: GetMetrics, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut services: Vec<ServiceMetrics> = self
+            .services
+            .values()
+            .map(|v| ServiceMetrics {
+                id: v.model.id,
+                name: v.model.name.clone(),
+                state: v.state.get_state(),
+                uptime: v.uptime(),
+                restarts: v.restarts,
+                crashes: v.crashes,
+                last_exit_code: v.crash_code.load(Ordering::Acquire),
+                console_lines: v.tty.lines.load(Ordering::Relaxed),
+                console_bytes: v.tty.bytes.load(Ordering::Relaxed),
+                dropped_lines: v.tty.dropped.load(Ordering::Relaxed),
+            })
+            .collect();
+        services.sort_by_key(|v| v.id);
+        Ok(DaemonMetrics {
+            services,
+            logins: DB.count_logins()?,
+            db_size: DB.size_on_disk()?,
+        })
+    }
+}
+
 impl Handler<GetLogConsole> for ServiceController {
     type Result = Result<ConsoleOutput, ControllerError>;
     fn handle(&mut self, msg: GetLogConsole, _ctx: &mut Context<Self>) -> Self::Result {
@@ -422,10 +1065,37 @@ impl Handler<GetLogLatest> for ServiceController {
     }
 }
 
+impl Handler<GetLogFiles> for ServiceController {
+    type Result = Result<Vec<LogFileInfo>, ControllerError>;
+    fn handle(&mut self, msg: GetLogFiles, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(instance) = self.services.get(&msg.id) {
+            let base = logfile::file_base(msg.id, &instance.model.name);
+            logfile::rotated_files(Path::new(LOG_DIR), &base)
+                .map_err(ControllerError::LogFileIOError)
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
+impl Handler<GetLogFile> for ServiceController {
+    type Result = Result<PathBuf, ControllerError>;
+    fn handle(&mut self, msg: GetLogFile, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(instance) = self.services.get(&msg.id) {
+            let base = logfile::file_base(msg.id, &instance.model.name);
+            logfile::rotated_file_path(Path::new(LOG_DIR), &base, &msg.file)
+                .map_err(ControllerError::LogFileIOError)?
+                .ok_or(ControllerError::InvalidLogFile(msg.file))
+        } else {
+            Err(ControllerError::InvalidInstance(msg.id))
+        }
+    }
+}
+
 impl Handler<LoadServices> for ServiceController {
     type Result = ();
     fn handle(&mut self, msg: LoadServices, ctx: &mut Context<Self>) {
-        if self.load_services(msg.data).is_ok() {
+        if self.load_services(msg.data, msg.cgroup_root).is_ok() {
             for (key, val) in self.services.iter() {
                 if val.model.autostart {
                     trace!("Autostarting {}", key);
@@ -450,19 +1120,293 @@ impl Handler<LoadServices> for ServiceController {
 
 type LoadedService = Instance;
 
+type TtyBuffer = ArrayDeque<[ConsoleType<Vec<u8>>; 2048], Wrapping>;
+
+/// Console of an instance, ring buffer with optional log file
+struct Tty {
+    buffer: RwLock<TtyBuffer>,
+    file: Option<Mutex<LogFileWriter>>,
+    limiter: Option<Mutex<RateLimiter>>,
+    /// Output lines dropped by the rate limit
+    dropped: AtomicU64,
+    /// Lines captured since daemon start
+    lines: AtomicU64,
+    /// Bytes captured since daemon start
+    bytes: AtomicU64,
+}
+
+impl Tty {
+    fn new(file: Option<LogFileWriter>, limit: Option<OutputLimit>) -> Self {
+        Self {
+            buffer: RwLock::new(ArrayDeque::new()),
+            file: file.map(Mutex::new),
+            limiter: limit.map(|l| Mutex::new(RateLimiter::new(l))),
+            dropped: AtomicU64::new(0),
+            lines: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+    /// Append line to buffer and log file
+    fn push(&self, line: ConsoleType<Vec<u8>>) {
+        self.push_batch(vec![line]);
+    }
+    /// Append lines, taking the locks only once
+    fn push_batch(&self, lines: Vec<ConsoleType<Vec<u8>>>) {
+        if lines.is_empty() {
+            return;
+        }
+        let bytes: usize = lines.iter().map(line_len).sum();
+        self.lines.fetch_add(lines.len() as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(file) = self.file.as_ref() {
+            let mut file_w = file.lock().expect("Can't lock log file!");
+            for line in &lines {
+                if let Err(e) = file_w.write_line(line) {
+                    error!("Unable to write console log file: {}", e);
+                    break;
+                }
+            }
+        }
+        let mut buffer_w = self.buffer.write().expect("Can't write buffer!");
+        buffer_w.extend_back(lines);
+    }
+    /// Check output line of `len` bytes against the rate limit
+    ///
+    /// Adds a summary of suppressed lines to `batch` once a limit window is over.
+    fn admit(&self, len: usize, batch: &mut Vec<ConsoleType<Vec<u8>>>) -> bool {
+        let limiter = match self.limiter.as_ref() {
+            Some(v) => v,
+            None => return true,
+        };
+        let mut limiter_w = limiter.lock().expect("Can't lock limiter!");
+        let (admitted, suppressed) = limiter_w.admit(len, Instant::now());
+        if let Some(amount) = suppressed {
+            batch.push(suppressed_line(amount));
+        }
+        if !admitted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        admitted
+    }
+    /// Summary of lines suppressed in the current limit window
+    fn flush_suppressed(&self) {
+        if let Some(limiter) = self.limiter.as_ref() {
+            let suppressed = limiter.lock().expect("Can't lock limiter!").take_dropped();
+            if let Some(amount) = suppressed {
+                self.push(suppressed_line(amount));
+            }
+        }
+    }
+}
+
+/// Text length of a console line in bytes
+fn line_len(line: &ConsoleType<Vec<u8>>) -> usize {
+    match line {
+        ConsoleType::Stdin(v)
+        | ConsoleType::Stdout(v)
+        | ConsoleType::Stderr(v)
+        | ConsoleType::State(v) => v.len(),
+        ConsoleType::StdoutStyled(spans) | ConsoleType::StderrStyled(spans) => {
+            spans.iter().map(|s| s.text.len()).sum()
+        }
+    }
+}
+
+fn suppressed_line(amount: u64) -> ConsoleType<Vec<u8>> {
+    ConsoleType::State(format!("{} lines suppressed", amount).into_bytes())
+}
+
+/// Writer for the stdin of a running process, pipe or pty master
+type StdinWriter = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// Pty master handles (reader, writer)
+type PtyMaster = (std::fs::File, std::fs::File);
+
+/// Output stream of a process
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Output triggers of a service, cooldowns persist across runs
+struct Triggers {
+    id: SID,
+    triggers: Vec<Trigger>,
+    last_fired: Mutex<Vec<Option<Instant>>>,
+}
+
+impl Triggers {
+    fn new(id: SID, triggers: Vec<Trigger>) -> Self {
+        Self {
+            id,
+            last_fired: Mutex::new(vec![None; triggers.len()]),
+            triggers,
+        }
+    }
+
+    /// Check raw output line, fires matching triggers not in cooldown
+    fn check(&self, line: &[u8], stream: Stream, addr: &Addr<ServiceController>) {
+        if self.triggers.is_empty() {
+            return;
+        }
+        let stripped = ansi_esc::strip(line).unwrap();
+        let text = String::from_utf8_lossy(&stripped);
+        let text = text.trim_end_matches(&['\n', '\r'][..]);
+        let now = Instant::now();
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            match (trigger.stream, stream) {
+                (TriggerStream::Stdout, Stream::Stderr)
+                | (TriggerStream::Stderr, Stream::Stdout) => continue,
+                _ => (),
+            }
+            if !trigger.pattern.is_match(text) {
+                continue;
+            }
+            let mut last_fired = self.last_fired.lock().expect("Can't lock triggers!");
+            if let Some(last) = last_fired[i] {
+                if now.duration_since(last) < Duration::from_secs(trigger.cooldown_secs) {
+                    continue;
+                }
+            }
+            last_fired[i] = Some(now);
+            addr.do_send(TriggerFired {
+                id: self.id,
+                trigger: i,
+                line: text.to_owned(),
+            });
+        }
+    }
+}
+
+/// Lines to insert at once when output is already buffered
+const OUTPUT_BATCH: usize = 256;
+
+/// Convert raw output line to console entry
+fn output_line(
+    mut line: &[u8],
+    stream: Stream,
+    styles: Option<&mut StyleParser>,
+) -> ConsoleType<Vec<u8>> {
+    while let Some((&b'\n', rest)) | Some((&b'\r', rest)) = line.split_last() {
+        line = rest;
+    }
+    match (styles, stream) {
+        (Some(p), Stream::Stdout) => ConsoleType::StdoutStyled(p.line(line)),
+        (Some(p), Stream::Stderr) => ConsoleType::StderrStyled(p.line(line)),
+        (None, Stream::Stdout) => ConsoleType::Stdout(ansi_esc::strip(line).unwrap()),
+        (None, Stream::Stderr) => ConsoleType::Stderr(ansi_esc::strip(line).unwrap()),
+    }
+}
+
+/// Read process output linewise into the console until EOF
+///
+/// Lines already available are inserted as batch, rate limits apply before parsing.
+async fn read_output<R>(
+    reader: R,
+    tty: Arc<Tty>,
+    stream: Stream,
+    ansi: AnsiMode,
+    triggers: Arc<Triggers>,
+    watchdog: Arc<OutputWatchdog>,
+    addr: Addr<ServiceController>,
+) where
+    R: AsyncRead + Unpin,
+{
+    let mut styles = match ansi {
+        AnsiMode::Styled => Some(StyleParser::new()),
+        AnsiMode::Strip => None,
+    };
+    let mut reader = BufReader::new(reader);
+    // partial lines stay in here when a read is pending
+    let mut line = Vec::new();
+    let mut batch = Vec::new();
+    let mut eof = false;
+    while !eof {
+        let mut res = reader.read_until(b'\n', &mut line).await;
+        loop {
+            match res {
+                Ok(0) => eof = true,
+                Ok(_) => (),
+                Err(e) => {
+                    if !output_closed(&e) {
+                        error!("Error handling output: {}", e);
+                    }
+                    eof = true;
+                }
+            }
+            if !line.is_empty() {
+                watchdog.line(&line, Instant::now());
+                triggers.check(&line, stream, &addr);
+                if tty.admit(line.len(), &mut batch) {
+                    batch.push(output_line(&line, stream, styles.as_mut()));
+                }
+                line.clear();
+            }
+            if eof || batch.len() >= OUTPUT_BATCH {
+                break;
+            }
+            match reader.read_until(b'\n', &mut line).now_or_never() {
+                Some(v) => res = v,
+                None => break,
+            }
+        }
+        tty.push_batch(mem::take(&mut batch));
+    }
+    tty.flush_suppressed();
+}
+
+#[cfg(unix)]
+fn output_closed(e: &::std::io::Error) -> bool {
+    pty::is_closed(e)
+}
+
+#[cfg(not(unix))]
+fn output_closed(_e: &::std::io::Error) -> bool {
+    false
+}
+
 struct Instance {
     model: Service,
     running: Arc<AtomicBool>,
-    tty: Arc<RwLock<ArrayDeque<[ConsoleType<Vec<u8>>; 2048], Wrapping>>>,
+    tty: Arc<Tty>,
     state: StateFlag,
     crash_code: Arc<AtomicI32>,
+    /// Terminating signal of the last run, 0 if none
+    crash_signal: Arc<AtomicI32>,
+    /// Whether the last run dumped core
+    core_dumped: Arc<AtomicBool>,
     kill_handle: Option<tokio::sync::oneshot::Sender<()>>,
-    stdin: Option<tokio::sync::mpsc::Sender<String>>,
+    stdin: Option<tokio::sync::mpsc::Sender<QueuedInput>>,
     start_time: Option<u64>,
     end_time: Option<u64>,
+    triggers: Arc<Triggers>,
+    watchdog: Arc<OutputWatchdog>,
+    /// Start again after the current run ended
+    restart_pending: bool,
+    /// Pending restart is from a restart command
+    restart_cmd: bool,
+    /// Current run is stopped for exceeding its max runtime
+    timed_out: bool,
+    /// pre_start hook running
+    starting: bool,
+    /// Incremented on every process start
+    run_id: u64,
+    /// Automatic restarts since daemon start
+    restarts: u64,
+    /// Crashes since daemon start
+    crashes: u64,
+    /// Process ID while running
+    pid: Option<u32>,
+    usage: UsageHistory,
+    memory_guard: MemoryGuard,
+    /// Parent of the service cgroup
+    cgroup_root: Option<PathBuf>,
+    /// cgroup of the last run
+    cgroup: Option<Cgroup>,
 }
 
-#[derive(PartialEq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum State {
     Stopped = 0,
     Running = 1,
@@ -470,6 +1414,9 @@ pub enum State {
     Crashed = 3,
     Stopping = 4,
     Killed = 5,
+    Paused = 6,
+    /// Stopped for exceeding its max runtime
+    TimedOut = 7,
 }
 
 // derived from https://gist.github.com/polypus74/eabc7bb00873e6b90abe230f9e632989
@@ -504,12 +1451,191 @@ impl From<usize> for State {
             3 => Crashed,
             4 => Stopping,
             5 => Killed,
+            6 => Paused,
+            7 => TimedOut,
             _ => unreachable!(),
         }
     }
 }
 
 impl Instance {
+    /// Usage counters of the cgroup
+    fn cgroup_usage(&self) -> Option<CgroupUsage> {
+        self.cgroup.as_ref().and_then(|v| v.usage().ok())
+    }
+    /// Drop handles of the ended process
+    fn exited(&mut self) {
+        self.pid = None;
+        self.cgroup = None;
+    }
+    /// Memory restart threshold, if configured
+    fn memory_limit(&self) -> Option<MemoryLimit> {
+        self.model.restart_on_memory_mb.map(|mb| MemoryLimit {
+            bytes: mb * 1024 * 1024,
+            grace: Duration::from_secs(self.model.memory_restart_grace_secs),
+            window: self.model.memory_restart_window,
+        })
+    }
+    /// Latest usage sample, if running
+    fn current_usage(&self) -> Option<UsageSample> {
+        self.pid.and(self.usage.latest().cloned())
+    }
+    /// Kill the process, resumes it first if paused
+    ///
+    /// Returns false if there is no process to kill.
+    fn kill(&mut self) -> bool {
+        if self.state.get_state() == State::Paused {
+            // stopped processes of the group would stay around otherwise
+            if let Err(e) = self.set_paused(false) {
+                warn!("Can't resume {} before kill: {}", self.model.name, e);
+            }
+        }
+        match self.kill_handle.take() {
+            Some(v) => {
+                let _ = v.send(());
+                true
+            }
+            None => false,
+        }
+    }
+    /// Send the watchdog probe to stdin
+    fn probe_output(&mut self) {
+        let (stdin, probe) = match (
+            self.stdin.as_mut(),
+            self.model.output_watchdog_probe.as_ref(),
+        ) {
+            (Some(stdin), Some(probe)) => (stdin, probe),
+            _ => return,
+        };
+        self.watchdog.start_probe();
+        let display = stdin::display(probe.as_bytes(), false, &self.model.stdin_mask).0;
+        if let Err(e) = stdin.try_send(QueuedInput {
+            input: StdinMsg::line(probe),
+            display,
+        }) {
+            warn!("Can't send watchdog probe: {}", e);
+        }
+    }
+    /// Check whether the soft-stop command can be sent
+    fn can_soft_stop(&self) -> Result<(), ControllerError> {
+        if self.state.get_state() == State::Paused {
+            return Err(ControllerError::ServicePaused);
+        }
+        if self.stdin.is_none() {
+            return Err(ControllerError::NoServiceHandle);
+        }
+        if self.model.soft_stop.is_none() {
+            return Err(ControllerError::NoSoftStop);
+        }
+        Ok(())
+    }
+    /// Send the soft-stop command
+    fn soft_stop(&mut self) -> Result<(), ControllerError> {
+        self.can_soft_stop()?;
+        let (stdin, stop_msg) = match (self.stdin.as_mut(), self.model.soft_stop.as_ref()) {
+            (Some(stdin), Some(stop_msg)) => (stdin, stop_msg),
+            _ => unreachable!("checked by can_soft_stop"),
+        };
+        let display = stdin::display(stop_msg.as_bytes(), false, &self.model.stdin_mask).0;
+        if let Err(e) = stdin.try_send(QueuedInput {
+            input: StdinMsg::line(stop_msg),
+            display,
+        }) {
+            warn!("Can't soft-stop process: {}", e);
+        }
+        self.state.set_state(State::Stopping);
+        Ok(())
+    }
+    /// Pause or resume all processes of the service
+    ///
+    /// Uses the cgroup freezer if available, otherwise SIGSTOP/SIGCONT on the process group.
+    fn set_paused(&self, paused: bool) -> Result<(), ::std::io::Error> {
+        if let Some(cgroup) = self.cgroup.as_ref() {
+            match cgroup.freeze(paused) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("cgroup freezer unavailable, using signals: {}", e),
+            }
+        }
+        #[cfg(unix)]
+        {
+            let pid = self.pid.ok_or_else(|| {
+                ::std::io::Error::new(::std::io::ErrorKind::NotFound, "no running process")
+            })?;
+            let sig = if paused { libc::SIGSTOP } else { libc::SIGCONT };
+            signal::send_group(pid, sig)
+        }
+        #[cfg(not(unix))]
+        Err(::std::io::Error::new(
+            ::std::io::ErrorKind::Other,
+            "pausing is not supported on this platform",
+        ))
+    }
+    /// Send signal to the main process if allowed, returns its canonical name
+    #[cfg(unix)]
+    fn send_signal(&self, name: &str) -> Result<&'static str, ControllerError> {
+        let (name, sig) = match signal::lookup(name) {
+            Some(v) => v,
+            None => return Err(ControllerError::SignalDenied(name.to_owned())),
+        };
+        if !self
+            .model
+            .signals
+            .iter()
+            .any(|v| signal::lookup(v).filter(|(v, _)| *v == name).is_some())
+        {
+            return Err(ControllerError::SignalDenied(name.to_owned()));
+        }
+        let pid = self.pid.ok_or(ControllerError::NoServiceHandle)?;
+        signal::send(pid, sig).map_err(ControllerError::SignalIOError)?;
+        Ok(name)
+    }
+    #[cfg(not(unix))]
+    fn send_signal(&self, name: &str) -> Result<&'static str, ControllerError> {
+        Err(ControllerError::SignalDenied(name.to_owned()))
+    }
+    /// Log action of a crash, naming the limit that caused it
+    fn crash_action(&self) -> LogAction {
+        if self.cgroup.as_ref().filter(|v| v.oom_killed()).is_some() {
+            self.tty
+                .push(ConsoleType::State(b"Killed by cgroup OOM killer".to_vec()));
+            return LogAction::ServiceOomKilled;
+        }
+        match self.limit_exceeded() {
+            Some(limit) => {
+                self.tty.push(ConsoleType::State(
+                    format!("Resource limit: {}", limit).into_bytes(),
+                ));
+                LogAction::ServiceLimitExceeded(limit.to_owned())
+            }
+            None => LogAction::ServiceCrashed(self.crash_code.load(Ordering::Acquire)),
+        }
+    }
+    /// Details of the last process exit
+    fn exit_details(&self) -> ExitDetails {
+        let signal = self.crash_signal.load(Ordering::Acquire);
+        ExitDetails {
+            signal: if signal == 0 { None } else { Some(signal) },
+            core_dumped: self.core_dumped.load(Ordering::Acquire),
+            runtime_secs: self.uptime(),
+            restarts: self.restarts,
+        }
+    }
+    /// Exit code of the last run, None if terminated by a signal
+    fn exit_code(&self) -> Option<i32> {
+        match self.crash_signal.load(Ordering::Acquire) {
+            0 => Some(self.crash_code.load(Ordering::Acquire)),
+            _ => None,
+        }
+    }
+    /// Resource limit that killed the last run
+    #[cfg(unix)]
+    fn limit_exceeded(&self) -> Option<&'static str> {
+        rlimit::exceeded(self.crash_signal.load(Ordering::Acquire))
+    }
+    #[cfg(not(unix))]
+    fn limit_exceeded(&self) -> Option<&'static str> {
+        None
+    }
     fn uptime(&self) -> u64 {
         let subtrahend = match self.end_time {
             Some(v) => v,
@@ -518,35 +1644,69 @@ impl Instance {
         self.start_time.as_ref().map_or(0, |v| subtrahend - v)
     }
     fn console_output(&self) -> ConsoleOutput {
-        let tty_r = self.tty.read().expect("Can't read tty!");
+        let tty_r = self.tty.buffer.read().expect("Can't read tty!");
         let msg = tty_r
             .iter()
-            .map(|s| match s {
-                ConsoleType::State(s) => {
-                    ConsoleType::State(String::from_utf8_lossy(&s).into_owned())
-                }
-                ConsoleType::Stderr(s) => {
-                    ConsoleType::Stderr(String::from_utf8_lossy(&s).into_owned())
-                }
-                ConsoleType::Stdout(s) => {
-                    ConsoleType::Stdout(String::from_utf8_lossy(&s).into_owned())
-                }
-                ConsoleType::Stdin(s) => {
-                    ConsoleType::Stdin(String::from_utf8_lossy(&s).into_owned())
-                }
-            })
+            .map(|s| s.map(|v| String::from_utf8_lossy(v).into_owned()))
             .collect::<Vec<_>>();
         msg
     }
-    /// Run instance, outer catch function to log startup errors to tty
+    /// Run instance, starting with the pre_start hook if configured
     fn run(&mut self, addr: Addr<ServiceController>) -> Result<(), ::std::io::Error> {
+        if self.model.enabled
+            && self.model.pre_start.is_some()
+            && !self.running.load(Ordering::Acquire)
+        {
+            if !self.starting {
+                self.starting = true;
+                self.start_hook(HookStage::PreStart, addr);
+            }
+            return Ok(());
+        }
+        self.spawn(addr)
+    }
+    /// Run hook of stage in the background, reports back with HookFinished
+    ///
+    /// Output is passed to the tty, prefixed with the hook name.
+    fn start_hook(&self, stage: HookStage, addr: Addr<ServiceController>) {
+        let hook = match stage.hook(&self.model) {
+            Some(v) => v.clone(),
+            None => return,
+        };
+        let name = stage.name();
+        self.tty.push(ConsoleType::State(
+            format!("Running {} hook", name).into_bytes(),
+        ));
+        let (id, run) = (self.model.id, self.run_id);
+        let tty = self.tty.clone();
+        let dir = self.workdir();
+        let envs = self.envs();
+        spawn(async move {
+            let result = match dir {
+                Ok(dir) => hook::run(hook, dir.into(), envs, move |line| {
+                    tty.push(ConsoleType::State(
+                        format!("[{}] {}", name, line).into_bytes(),
+                    ))
+                })
+                .await
+                .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            addr.do_send(HookFinished {
+                id,
+                run,
+                stage,
+                result,
+            });
+        });
+    }
+    /// Spawn process, outer catch function to log startup errors to tty
+    fn spawn(&mut self, addr: Addr<ServiceController>) -> Result<(), ::std::io::Error> {
         let res = self.run_internal(addr);
         if let Err(e) = &res {
-            let mut buffer_w = self.tty.write().expect("Can't write buffer!");
-            buffer_w.push_back(ConsoleType::State(
+            self.tty.push(ConsoleType::State(
                 format!("Can't start instance: {}", e).into_bytes(),
             ));
-            drop(buffer_w);
             ServiceController::log(
                 NewLogEntry::new(LogAction::ServiceStartFailed(format!("{}", e)), None),
                 self.model.id,
@@ -587,6 +1747,82 @@ impl Instance {
         })
     }
 
+    /// Additional environment of the process and hooks
+    fn envs(&self) -> Vec<(&'static str, String)> {
+        match self.model.instance() {
+            Some(index) => vec![("SD_INSTANCE", index.to_string())],
+            None => Vec::new(),
+        }
+    }
+
+    /// Setup command for spawning, returns pty master handles in tty mode
+    fn build_command(&mut self) -> Result<(Command, Option<PtyMaster>), ::std::io::Error> {
+        let mut cmd = Command::new(self.command()?);
+        //TODO: fix this to use better ENV
+        // cmd.env_clear();
+        cmd.kill_on_drop(true);
+        cmd.args(&self.model.args);
+        cmd.envs(self.envs());
+        cmd.current_dir(self.workdir()?);
+        if let Some(limits) = self.model.rlimits {
+            #[cfg(unix)]
+            unsafe {
+                cmd.pre_exec(move || rlimit::apply(&limits));
+            }
+            #[cfg(not(unix))]
+            return Err(::std::io::Error::new(
+                ::std::io::ErrorKind::Other,
+                "rlimits are not supported on this platform",
+            ));
+        }
+        if let Some(limits) = self.model.cgroup.as_ref() {
+            #[cfg(unix)]
+            {
+                let root = self.cgroup_root.as_ref().ok_or_else(|| {
+                    ::std::io::Error::new(::std::io::ErrorKind::NotFound, "no cgroups root set")
+                })?;
+                let cgroup = Cgroup::setup(root, self.model.id, limits)?;
+                let procs = cgroup.procs()?;
+                unsafe {
+                    cmd.pre_exec(move || cgroup::join(&procs));
+                }
+                self.cgroup = Some(cgroup);
+            }
+            #[cfg(not(unix))]
+            return Err(::std::io::Error::new(
+                ::std::io::ErrorKind::Other,
+                "cgroups are not supported on this platform",
+            ));
+        }
+        if !self.model.tty {
+            // own process group for signals to the whole service
+            #[cfg(unix)]
+            unsafe {
+                cmd.pre_exec(signal::own_group);
+            }
+            cmd.stderr(Stdio::piped());
+            cmd.stdout(Stdio::piped());
+            cmd.stdin(Stdio::piped());
+            return Ok((cmd, None));
+        }
+        #[cfg(unix)]
+        {
+            let pty = Pty::open(self.model.tty_cols, self.model.tty_rows)?;
+            cmd.stdin(pty.stdio()?);
+            cmd.stdout(pty.stdio()?);
+            cmd.stderr(pty.stdio()?);
+            unsafe {
+                cmd.pre_exec(pty::setup_child);
+            }
+            Ok((cmd, Some((pty.master()?, pty.master()?))))
+        }
+        #[cfg(not(unix))]
+        Err(::std::io::Error::new(
+            ::std::io::ErrorKind::Other,
+            "tty mode is not supported on this platform",
+        ))
+    }
+
     /// real service starter
     fn run_internal(&mut self, addr: Addr<ServiceController>) -> Result<(), ::std::io::Error> {
         if self.model.enabled
@@ -595,22 +1831,16 @@ impl Instance {
                 .compare_and_swap(false, true, Ordering::Acquire)
         {
             trace!("Starting {}", self.model.name);
-            {
-                let mut buffer_w = self.tty.write().expect("Can't write buffer!");
-                buffer_w.push_back(ConsoleType::State(
-                    format!("Starting {}", self.model.name).into_bytes(),
-                ));
-                drop(buffer_w);
-            }
-            let mut cmd = Command::new(self.command()?);
-            //TODO: fix this to use better ENV
-            // cmd.env_clear();
-            cmd.kill_on_drop(true);
-            cmd.args(&self.model.args);
-            cmd.current_dir(self.workdir()?);
-            cmd.stderr(Stdio::piped());
-            cmd.stdout(Stdio::piped());
-            cmd.stdin(Stdio::piped());
+            self.tty.push(ConsoleType::State(
+                format!("Starting {}", self.model.name).into_bytes(),
+            ));
+            let (mut cmd, pty) = match self.build_command() {
+                Ok(v) => v,
+                Err(e) => {
+                    self.running.store(false, Ordering::Release);
+                    return Err(e);
+                }
+            };
             self.state.set_state(State::Running);
             let mut child = match cmd.spawn() {
                 Ok(v) => v,
@@ -621,6 +1851,16 @@ impl Instance {
                     return Err(e.into());
                 }
             };
+            // close our copy of the slave side, otherwise reads never end
+            drop(cmd);
+            self.pid = Some(child.id());
+            self.run_id += 1;
+            // killed runs don't report their exit
+            self.crash_signal.store(0, Ordering::Release);
+            self.core_dumped.store(false, Ordering::Release);
+            self.watchdog.reset(Instant::now());
+            self.usage.restart();
+            self.memory_guard.reset();
             self.start_time = Some(get_system_time_64());
             self.end_time = None;
 
@@ -631,26 +1871,78 @@ impl Instance {
 
             let service_info = format!("{}-{}", self.model.id, self.model.name);
 
-            let mut stdin = child.stdin.take().unwrap();
-            let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(16);
+            let (mut stdin, stdout_fut, stderr_fut): (StdinWriter, _, _) = match pty {
+                Some((reader, writer)) => (
+                    Box::new(tokio::fs::File::from_std(writer)),
+                    read_output(
+                        tokio::fs::File::from_std(reader),
+                        self.tty.clone(),
+                        Stream::Stdout,
+                        self.model.ansi,
+                        self.triggers.clone(),
+                        self.watchdog.clone(),
+                        addr.clone(),
+                    )
+                    .boxed(),
+                    // merged into stdout by the terminal
+                    future::ready(()).boxed(),
+                ),
+                None => (
+                    Box::new(child.stdin.take().unwrap()),
+                    read_output(
+                        child.stdout.take().unwrap(),
+                        self.tty.clone(),
+                        Stream::Stdout,
+                        self.model.ansi,
+                        self.triggers.clone(),
+                        self.watchdog.clone(),
+                        addr.clone(),
+                    )
+                    .boxed(),
+                    read_output(
+                        child.stderr.take().unwrap(),
+                        self.tty.clone(),
+                        Stream::Stderr,
+                        self.model.ansi,
+                        self.triggers.clone(),
+                        self.watchdog.clone(),
+                        addr.clone(),
+                    )
+                    .boxed(),
+                ),
+            };
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<QueuedInput>(16);
             let buffer_c = self.tty.clone();
+            let tty_mode = self.model.tty;
             // handle stdin
             // doesn't end in join on child-kill, thus spawn on its own
             spawn(async move {
                 while let Some(msg) = rx.recv().await {
-                    let buffer_c2 = buffer_c.clone();
-                    let buffer_c3 = buffer_c.clone();
-                    let service_info = service_info.clone();
-                    match stdin.write_all(msg.as_bytes()).await {
+                    let data = match msg.input {
+                        StdinMsg::Data(data) => data,
+                        // closing the master would hang up the terminal
+                        StdinMsg::Close if tty_mode => vec![0x04],
+                        StdinMsg::Close => {
+                            if let Err(e) = stdin.shutdown().await {
+                                error!("Couldn't close stdin of {}: {}", service_info, e);
+                            }
+                            buffer_c.push(ConsoleType::State(b"Stdin closed".to_vec()));
+                            break;
+                        }
+                    };
+                    let res = match stdin.write_all(&data).await {
+                        Ok(()) => stdin.flush().await,
+                        Err(e) => Err(e),
+                    };
+                    match res {
                         Ok(()) => {
-                            let mut buffer_w = buffer_c2.write().expect("Can't write buffer!");
-                            buffer_w.push_back(ConsoleType::Stdin(msg.into_bytes()));
+                            buffer_c.push(ConsoleType::Stdin(msg.display.into_bytes()));
                         }
                         Err(e) => {
                             error!("Couldn't write to stdin of {}: {}", service_info, e);
-                            let mut buffer_w = buffer_c3.write().expect("Can't write buffer!");
-                            buffer_w.push_back(ConsoleType::State(
-                                format!("Couldn't write to stdout! \"{}\"", msg).into_bytes(),
+                            buffer_c.push(ConsoleType::State(
+                                format!("Couldn't write to stdin! \"{}\"", msg.display)
+                                    .into_bytes(),
                             ));
                         }
                     }
@@ -658,63 +1950,38 @@ impl Instance {
             });
             self.stdin = Some(tx);
 
-            let buffer_c = self.tty.clone();
-            let stdout = child.stdout.take().unwrap();
-            // handle stdout
-            let stdout_fut = async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Some(l) = lines.next().await {
-                    match l {
-                        Err(e) => error!("Error handling stdout: {}", e),
-                        Ok(line) => {
-                            let mut buffer_w = buffer_c.write().expect("Can't write buffer!");
-                            buffer_w.push_back(ConsoleType::Stdout(ansi_esc::strip(line).unwrap()));
-                        }
-                    }
-                }
-            };
-
-            let buffer_c = self.tty.clone();
-            let stderr = child.stderr.take().unwrap();
-            // handle stderr
-            let stderr_fut = async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Some(l) = lines.next().await {
-                    match l {
-                        Ok(line) => {
-                            let mut buffer_w = buffer_c.write().expect("Can't write buffer!");
-                            buffer_w.push_back(ConsoleType::Stderr(ansi_esc::strip(line).unwrap()));
-                        }
-                        Err(e) => error!("Error handling stderr: {}", e),
-                    }
-                }
-            };
-
             let buffer_c = self.tty.clone();
             let state_c = self.state.clone();
             let crash_code = self.crash_code.clone();
+            let crash_signal = self.crash_signal.clone();
+            let core_dumped = self.core_dumped.clone();
+            let success_codes = self.model.success_exit_codes.clone();
             // handle child exit-return
             let child_fut = async move {
                 let result = child.await;
-                let mut buffer_w = buffer_c.write().expect("Can't write buffer!");
                 match result {
                     Ok(state) => {
                         #[cfg(target_family = "unix")]
                         let code_formated = sysexit::from_status(state);
                         #[cfg(target_family = "windows")]
                         let code_formated = "";
-                        buffer_w.push_back(ConsoleType::State(
+                        buffer_c.push(ConsoleType::State(
                             format!("Process ended with signal {}({:?})", state, code_formated)
                                 .into_bytes(),
                         ));
                         if let Some(code) = state.code() {
                             crash_code.store(code, Ordering::Release);
                         }
+                        #[cfg(unix)]
+                        let signal = std::os::unix::process::ExitStatusExt::signal(&state);
+                        #[cfg(not(unix))]
+                        let signal: Option<i32> = None;
+                        crash_signal.store(signal.unwrap_or(0), Ordering::Release);
+                        #[cfg(unix)]
+                        core_dumped.store(signal::core_dumped(&state), Ordering::Release);
                         match state_c.get_state() {
-                            State::Running => {
-                                if state.success() {
+                            State::Running | State::Paused => {
+                                if state.code().filter(|c| success_codes.contains(c)).is_some() {
                                     state_c.set_state(State::Ended);
                                 } else {
                                     state_c.set_state(State::Crashed);
@@ -728,7 +1995,7 @@ impl Instance {
                         }
                     }
                     Err(e) => {
-                        buffer_w.push_back(ConsoleType::State(
+                        buffer_c.push(ConsoleType::State(
                             "Unable to read exit state!".to_string().into_bytes(),
                         ));
                         state_c.set_state(State::Crashed);
@@ -746,8 +2013,7 @@ impl Instance {
                     _ = child_fut => (),
                     _ = rx.map_err(|_| ()).map(move |_| {
                             state_c.set_state(State::Killed);
-                            let mut buffer_w = buffer_c.write().expect("Can't write buffer!");
-                            buffer_w.push_back(ConsoleType::State(
+                            buffer_c.push(ConsoleType::State(
                                 String::from("Process killed").into_bytes(),
                             ));
                         }) => (),
@@ -778,16 +2044,70 @@ impl Instance {
 
 impl From<Service> for Instance {
     fn from(service: Service) -> Self {
+        let log_file = service.log_file.clone().map(|settings| {
+            LogFileWriter::new(LOG_DIR.into(), service.id, &service.name, settings)
+        });
+        let tty = Tty::new(log_file, service.output_limit.clone());
+        let triggers = Triggers::new(service.id, service.triggers.clone());
+        let watchdog = OutputWatchdog::new(service.output_watchdog_reply.clone());
         Self {
             model: service,
             running: Arc::new(AtomicBool::new(false)),
-            tty: Arc::new(RwLock::new(ArrayDeque::new())),
+            tty: Arc::new(tty),
             state: StateFlag::new(State::Stopped),
             kill_handle: None,
             crash_code: Arc::new(AtomicI32::new(0)),
+            crash_signal: Arc::new(AtomicI32::new(0)),
+            core_dumped: Arc::new(AtomicBool::new(false)),
             stdin: None,
             start_time: None,
             end_time: None,
+            triggers: Arc::new(triggers),
+            watchdog: Arc::new(watchdog),
+            restart_pending: false,
+            restart_cmd: false,
+            timed_out: false,
+            starting: false,
+            run_id: 0,
+            restarts: 0,
+            crashes: 0,
+            pid: None,
+            usage: UsageHistory::default(),
+            memory_guard: MemoryGuard::default(),
+            cgroup_root: None,
+            cgroup: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::{CgroupLimits, Settings};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_exited_cgroup_usage() {
+        let mut settings = Settings::new_opt(Some("tests/double_id.valid.toml")).unwrap();
+        let mut instance = Instance::from(settings.services.remove(0));
+        let root = tempdir().unwrap();
+        let limits = CgroupLimits {
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None,
+        };
+        let cgroup = Cgroup::setup(root.path(), 0, &limits).unwrap();
+        let dir = root.path().join("service-0");
+        fs::write(dir.join("memory.current"), "4096\n").unwrap();
+        fs::write(dir.join("cpu.stat"), "usage_usec 1500\n").unwrap();
+        fs::write(dir.join("pids.current"), "1\n").unwrap();
+        instance.cgroup = Some(cgroup);
+        instance.pid = Some(1);
+        assert!(instance.cgroup_usage().is_some());
+
+        instance.exited();
+        assert_eq!(None, instance.pid);
+        assert_eq!(None, instance.cgroup_usage());
+    }
+}
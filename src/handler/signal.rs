@@ -0,0 +1,119 @@
+//! Signals to service processes
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Signals users can send, SIGKILL, SIGSTOP and SIGCONT have their own commands
+const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGABRT", libc::SIGABRT),
+    ("SIGALRM", libc::SIGALRM),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGWINCH", libc::SIGWINCH),
+];
+
+/// Signals processes commonly die of, not available to users
+const EXIT_SIGNALS: &[(&str, libc::c_int)] = &[
+    ("SIGKILL", libc::SIGKILL),
+    ("SIGSEGV", libc::SIGSEGV),
+    ("SIGBUS", libc::SIGBUS),
+    ("SIGFPE", libc::SIGFPE),
+    ("SIGILL", libc::SIGILL),
+    ("SIGPIPE", libc::SIGPIPE),
+];
+
+/// Canonical name and number of a signal
+///
+/// Accepts names with or without `SIG` prefix, in any case.
+pub fn lookup(name: &str) -> Option<(&'static str, libc::c_int)> {
+    let mut name = name.to_ascii_uppercase();
+    if !name.starts_with("SIG") {
+        name.insert_str(0, "SIG");
+    }
+    SIGNALS.iter().find(|(v, _)| *v == name).cloned()
+}
+
+/// Name of a signal number
+pub fn name(signal: libc::c_int) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .chain(EXIT_SIGNALS.iter())
+        .find(|(_, v)| *v == signal)
+        .map(|(v, _)| *v)
+}
+
+/// Send signal to a single process
+pub fn send(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether a process dumped core on termination
+pub fn core_dumped(status: &ExitStatus) -> bool {
+    libc::WCOREDUMP(status.into_raw())
+}
+
+/// Put the calling process into its own process group
+///
+/// To be called in the child before exec, so signals to the group don't reach SD.
+pub fn own_group() -> io::Result<()> {
+    if unsafe { libc::setpgid(0, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send signal to the process group led by pid
+pub fn send_group(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::killpg(pid as libc::pid_t, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(Some(("SIGHUP", libc::SIGHUP)), lookup("SIGHUP"));
+        assert_eq!(Some(("SIGUSR1", libc::SIGUSR1)), lookup("usr1"));
+        assert_eq!(None, lookup("SIGKILL"));
+        assert_eq!(None, lookup("FOO"));
+    }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(Some("SIGTERM"), name(libc::SIGTERM));
+        assert_eq!(Some("SIGSEGV"), name(libc::SIGSEGV));
+        assert_eq!(None, name(0));
+    }
+
+    #[test]
+    fn test_core_dumped() {
+        assert!(core_dumped(&ExitStatus::from_raw(0x80 | libc::SIGSEGV)));
+        assert!(!core_dumped(&ExitStatus::from_raw(libc::SIGSEGV)));
+        assert!(!core_dumped(&ExitStatus::from_raw(0)));
+    }
+
+    #[test]
+    fn test_send_group() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        unsafe {
+            cmd.pre_exec(own_group);
+        }
+        let mut child = cmd.spawn().unwrap();
+        send_group(child.id(), libc::SIGTERM).unwrap();
+        assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+    }
+}
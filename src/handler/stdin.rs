@@ -0,0 +1,327 @@
+//! Stdin input handling and escape encoding
+use crate::settings::{Pattern, StdinPolicy};
+use regex::Captures;
+use std::borrow::Cow;
+use std::str;
+
+/// Input for the stdin of a process
+#[derive(Debug, PartialEq)]
+pub enum StdinMsg {
+    /// Bytes to write as they are
+    Data(Vec<u8>),
+    /// Close stdin, sends EOF (Ctrl-D in tty mode)
+    Close,
+}
+
+impl StdinMsg {
+    /// Text line, terminated by a newline
+    pub fn line(input: &str) -> Self {
+        let mut data = input.as_bytes().to_vec();
+        data.push(b'\n');
+        StdinMsg::Data(data)
+    }
+}
+
+impl StdinMsg {
+    /// Input as text for pattern matching, without trailing newline
+    pub fn text(&self) -> Option<Cow<'_, str>> {
+        match self {
+            StdinMsg::Data(data) => Some(String::from_utf8_lossy(split_newline(data).0)),
+            StdinMsg::Close => None,
+        }
+    }
+}
+
+/// Placeholder for masked input
+pub const REDACTED: &str = "<redacted>";
+
+/// Input queued for the stdin writer
+pub struct QueuedInput {
+    pub input: StdinMsg,
+    /// Console representation, masked and without trailing newline
+    pub display: String,
+}
+
+/// Split off trailing newline
+fn split_newline(data: &[u8]) -> (&[u8], bool) {
+    match data.last() {
+        Some(b'\n') => (&data[..data.len() - 1], true),
+        _ => (data, false),
+    }
+}
+
+/// Replace matches of mask patterns, only the first group if the pattern has one
+fn mask(text: String, masks: &[Pattern]) -> String {
+    masks.iter().fold(text, |text, pattern| {
+        pattern
+            .0
+            .replace_all(&text, |caps: &Captures| {
+                let whole = caps.get(0).unwrap();
+                match caps.get(1) {
+                    Some(secret) => format!(
+                        "{}{}{}",
+                        &text[whole.start()..secret.start()],
+                        REDACTED,
+                        &text[secret.end()..whole.end()]
+                    ),
+                    None => REDACTED.to_owned(),
+                }
+            })
+            .into_owned()
+    })
+}
+
+/// Mask each line on its own, so anchored patterns match in multi-line input
+fn mask_lines(data: &[u8], masks: &[Pattern]) -> String {
+    let mut out = String::with_capacity(data.len());
+    let mut start = 0;
+    for (i, b) in data.iter().enumerate() {
+        if *b == b'\n' || *b == b'\r' {
+            let line = String::from_utf8_lossy(&data[start..i]).into_owned();
+            out.push_str(&escape_visible(mask(line, masks).as_bytes()));
+            out.push_str(&escape_visible(&[*b]));
+            start = i + 1;
+        }
+    }
+    let line = String::from_utf8_lossy(&data[start..]).into_owned();
+    out.push_str(&escape_visible(mask(line, masks).as_bytes()));
+    out
+}
+
+/// Printable input with secrets masked, returns (console line, log entry)
+///
+/// Only the log entry shows the trailing newline.
+pub fn display(data: &[u8], sensitive: bool, masks: &[Pattern]) -> (String, String) {
+    let (line, newline) = split_newline(data);
+    let console = if sensitive {
+        REDACTED.to_owned()
+    } else {
+        mask_lines(line, masks)
+    };
+    let log = if newline {
+        format!("{}\\n", console)
+    } else {
+        console.clone()
+    };
+    (console, log)
+}
+
+/// Check input against the stdin policy of a service
+///
+/// Every line of multi-line input has to pass. Closing stdin is only possible in enabled mode.
+pub fn allowed(policy: StdinPolicy, allow: &[Pattern], deny: &[Pattern], input: &StdinMsg) -> bool {
+    let text = match (policy, input.text()) {
+        (StdinPolicy::Disabled, _) => return false,
+        (StdinPolicy::Enabled, None) => return true,
+        (StdinPolicy::Restricted, None) => return false,
+        (_, Some(text)) => text,
+    };
+    let lines: Vec<&str> = text
+        .split(&['\n', '\r'][..])
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return line_allowed(policy, allow, deny, "");
+    }
+    lines
+        .iter()
+        .all(|line| line_allowed(policy, allow, deny, line))
+}
+
+/// Check a single input line, restricted mode rejects control characters except tabs
+fn line_allowed(policy: StdinPolicy, allow: &[Pattern], deny: &[Pattern], line: &str) -> bool {
+    if deny.iter().any(|p| p.is_match(line)) {
+        return false;
+    }
+    policy == StdinPolicy::Enabled
+        || (!line.chars().any(|c| c.is_control() && c != '\t')
+            && allow.iter().any(|p| p.is_match(line)))
+}
+
+/// Decode escape sequences into bytes
+///
+/// Supports `\n`, `\r`, `\t`, `\e`, `\0`, `\\` and `\xHH`, for example `\x03` for Ctrl-C.
+pub fn unescape(input: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => data.push(b'\n'),
+            Some('r') => data.push(b'\r'),
+            Some('t') => data.push(b'\t'),
+            Some('e') => data.push(0x1b),
+            Some('0') => data.push(0),
+            Some('\\') => data.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(v) if hex.len() == 2 => data.push(v),
+                    _ => return Err(format!("invalid hex escape \\x{}", hex)),
+                }
+            }
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err(String::from("incomplete escape at end of input")),
+        }
+    }
+    Ok(data)
+}
+
+fn escape_str(input: &str, out: &mut String) {
+    for c in input.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x1b' => out.push_str("\\e"),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() && (c as u32) < 0x100 => {
+                out.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Printable representation of bytes, reversible by [unescape]
+pub fn escape_visible(mut data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len());
+    loop {
+        match str::from_utf8(data) {
+            Ok(v) => {
+                escape_str(v, &mut out);
+                return out;
+            }
+            Err(e) => {
+                let (valid, rest) = data.split_at(e.valid_up_to());
+                escape_str(str::from_utf8(valid).unwrap(), &mut out);
+                let invalid = e.error_len().unwrap_or(rest.len());
+                for b in &rest[..invalid] {
+                    out.push_str(&format!("\\x{:02x}", b));
+                }
+                data = &rest[invalid..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn patterns(v: &[&str]) -> Vec<Pattern> {
+        v.iter()
+            .map(|p| Pattern(regex::Regex::new(p).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_allowed() {
+        let allow = patterns(&["^say "]);
+        let deny = patterns(&["^stop$", "secret"]);
+        let say = StdinMsg::line("say hi");
+        let stop = StdinMsg::line("stop");
+        let other = StdinMsg::Data(b"list".to_vec());
+
+        assert!(!allowed(StdinPolicy::Disabled, &allow, &deny, &say));
+        assert!(!allowed(StdinPolicy::Disabled, &[], &[], &StdinMsg::Close));
+
+        assert!(allowed(StdinPolicy::Enabled, &allow, &deny, &say));
+        assert!(allowed(StdinPolicy::Enabled, &allow, &deny, &other));
+        assert!(allowed(
+            StdinPolicy::Enabled,
+            &allow,
+            &deny,
+            &StdinMsg::Close
+        ));
+        assert!(!allowed(StdinPolicy::Enabled, &allow, &deny, &stop));
+
+        assert!(allowed(StdinPolicy::Restricted, &allow, &deny, &say));
+        assert!(!allowed(StdinPolicy::Restricted, &allow, &deny, &other));
+        assert!(!allowed(StdinPolicy::Restricted, &allow, &deny, &stop));
+        assert!(!allowed(
+            StdinPolicy::Restricted,
+            &allow,
+            &deny,
+            &StdinMsg::line("say secret")
+        ));
+        assert!(!allowed(
+            StdinPolicy::Restricted,
+            &allow,
+            &deny,
+            &StdinMsg::Close
+        ));
+    }
+
+    #[test]
+    fn test_allowed_multi_line() {
+        let allow = patterns(&["^say "]);
+        let deny = patterns(&["^stop$"]);
+        let check =
+            |policy, data: &[u8]| allowed(policy, &allow, &deny, &StdinMsg::Data(data.to_vec()));
+        assert!(check(StdinPolicy::Restricted, b"say hi\r\n"));
+        assert!(check(StdinPolicy::Restricted, b"say hi\nsay ho\n"));
+        assert!(!check(StdinPolicy::Restricted, b"say hi\nstop"));
+        assert!(!check(StdinPolicy::Restricted, b"say hi\rlist\n"));
+        assert!(!check(StdinPolicy::Restricted, b"say hi\x03"));
+        assert!(!check(StdinPolicy::Restricted, b"\n"));
+        assert!(!check(StdinPolicy::Enabled, b"list\nstop\n"));
+        assert!(check(StdinPolicy::Enabled, b"list\x03\n"));
+    }
+
+    #[test]
+    fn test_display() {
+        let masks = patterns(&["^login \\S+ (\\S+)$", "token=\\w+"]);
+        assert_eq!(
+            ("say hi".to_owned(), "say hi\\n".to_owned()),
+            display(b"say hi\n", false, &masks)
+        );
+        assert_eq!(
+            ("<redacted>".to_owned(), "<redacted>\\n".to_owned()),
+            display(b"hunter2\n", true, &masks)
+        );
+        assert_eq!(
+            "login admin <redacted>",
+            display(b"login admin hunter2\n", false, &masks).0
+        );
+        assert_eq!(
+            "a <redacted> b <redacted>",
+            display(b"a token=abc b token=def", false, &masks).1
+        );
+        assert_eq!(
+            "say hi\\nlogin admin <redacted>\\r\\nlogin root <redacted>",
+            display(
+                b"say hi\nlogin admin hunter2\r\nlogin root toor\n",
+                false,
+                &masks
+            )
+            .0
+        );
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(
+            b"a\nb\x03\x1b\\".to_vec(),
+            unescape("a\\nb\\x03\\e\\\\").unwrap()
+        );
+        assert_eq!("ä".as_bytes().to_vec(), unescape("ä").unwrap());
+        assert!(unescape("\\x0").is_err());
+        assert!(unescape("\\xzz").is_err());
+        assert!(unescape("\\q").is_err());
+        assert!(unescape("foo\\").is_err());
+    }
+
+    #[test]
+    fn test_escape_visible() {
+        assert_eq!("stop\\n", escape_visible(b"stop\n"));
+        assert_eq!("\\x03\\x04", escape_visible(b"\x03\x04"));
+        assert_eq!("ä\\xff\\\\", escape_visible(b"\xc3\xa4\xff\\"));
+        let data = b"\x00y\r\n\t\x1b[0m\xfe";
+        assert_eq!(data.to_vec(), unescape(&escape_visible(data)).unwrap());
+    }
+}
@@ -12,6 +12,7 @@ use actix::prelude::*;
 use actix_threadpool::run as blocking;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
 use std::iter;
 
 /// Initial root user
@@ -25,10 +26,17 @@ pub struct UserService {
     brcypt_cost: u32,
     login_max_age: u32,
     disable_totp: bool,
+    /// Template of service instances, which share its permissions
+    templates: HashMap<SID, SID>,
 }
 
 type UResult<T> = ::std::result::Result<T, UserError>;
 
+/// SID permissions are stored for, the template for instances
+fn perm_id(templates: &HashMap<SID, SID>, service: SID) -> SID {
+    *templates.get(&service).unwrap_or(&service)
+}
+
 impl UserService {
     /// Returns UID for session if currently fully logged in
     fn get_session_uid(&self, session: &str) -> UResult<UID> {
@@ -41,19 +49,27 @@ impl UserService {
             None => Err(UserError::InvalidSession),
         }
     }
+    /// See [perm_id](fn.perm_id.html)
+    fn perm_id(&self, service: SID) -> SID {
+        perm_id(&self.templates, service)
+    }
     /// Insert full service perms for admins
     ///
     /// We could also just check the admin state, but this would require a second lookup
     fn setup_admin_permissions(&self) {
         trace!("Setting up admin permissions");
-        actix::spawn(async {
+        let templates = self.templates.clone();
+        actix::spawn(async move {
             match ServiceController::from_registry()
                 .send(GetServiceIDs {})
                 .await
             {
                 Ok(services) => {
                     if let Err(e) = || -> Result<(), ControllerError> {
-                        let services = services?;
+                        let services: HashSet<SID> = services?
+                            .into_iter()
+                            .map(|id| perm_id(&templates, id))
+                            .collect();
                         for user in DB.get_perm_admin().map_err(ControllerError::from)? {
                             for service in services.iter() {
                                 DB.set_perm_service(user, *service, ServicePerm::all())
@@ -114,6 +130,7 @@ impl Default for UserService {
             brcypt_cost: 12,
             login_max_age: 3600,
             disable_totp: false,
+            templates: HashMap::new(),
         }
     }
 }
@@ -292,11 +309,13 @@ impl Handler<GetSessionServiceIDs> for UserService {
 
     fn handle(&mut self, msg: GetSessionServiceIDs, _ctx: &mut Context<Self>) -> Self::Result {
         let id = self.get_session_uid(&msg.session)?;
-        Ok(DB
-            .get_all_perm_service(id)?
-            .into_iter()
-            .map(|(k, _)| k)
-            .collect())
+        let perms = DB.get_all_perm_service(id)?;
+        let instances = self
+            .templates
+            .iter()
+            .filter(|(_, template)| perms.contains_key(template))
+            .map(|(instance, _)| *instance);
+        Ok(perms.keys().cloned().chain(instances).collect())
     }
 }
 
@@ -313,7 +332,7 @@ impl Handler<GetServicePermUser> for UserService {
     type Result = UResult<ServicePerm>;
 
     fn handle(&mut self, msg: GetServicePermUser, _ctx: &mut Context<Self>) -> Self::Result {
-        Ok(DB.get_perm_service(msg.user, msg.service)?)
+        Ok(DB.get_perm_service(msg.user, self.perm_id(msg.service))?)
     }
 }
 
@@ -321,7 +340,24 @@ impl Handler<SetServicePermUser> for UserService {
     type Result = UResult<()>;
 
     fn handle(&mut self, msg: SetServicePermUser, _ctx: &mut Context<Self>) -> Self::Result {
-        DB.set_perm_service(msg.user, msg.service, msg.perm)?;
+        DB.set_perm_service(msg.user, self.perm_id(msg.service), msg.perm)?;
+        Ok(())
+    }
+}
+
+impl Handler<GetNotifications> for UserService {
+    type Result = UResult<NotifyEvents>;
+
+    fn handle(&mut self, msg: GetNotifications, _ctx: &mut Context<Self>) -> Self::Result {
+        Ok(DB.get_notifications(msg.user, msg.service)?)
+    }
+}
+
+impl Handler<SetNotifications> for UserService {
+    type Result = UResult<()>;
+
+    fn handle(&mut self, msg: SetNotifications, _ctx: &mut Context<Self>) -> Self::Result {
+        DB.set_notifications(msg.user, msg.service, msg.events)?;
         Ok(())
     }
 }
@@ -331,7 +367,7 @@ impl Handler<GetServicePerm> for UserService {
 
     fn handle(&mut self, msg: GetServicePerm, _ctx: &mut Context<Self>) -> Self::Result {
         let uid = self.get_session_uid(&msg.session)?;
-        Ok((uid, DB.get_perm_service(uid, msg.service)?))
+        Ok((uid, DB.get_perm_service(uid, self.perm_id(msg.service))?))
     }
 }
 
@@ -467,6 +503,7 @@ impl Handler<SetConfig> for UserService {
         self.brcypt_cost = msg.cost;
         self.login_max_age = msg.max_session_age_secs;
         self.disable_totp = msg.disable_totp;
+        self.templates = msg.templates;
     }
 }
 
@@ -479,7 +516,7 @@ impl Handler<EditUser> for UserService {
             return Ok(false);
         }
         if let EditUserData::ServicePermission((service, perm)) = msg.data {
-            DB.set_perm_service(msg.user_uid, service, perm)?;
+            DB.set_perm_service(msg.user_uid, self.perm_id(service), perm)?;
         } else {
             let mut user = DB.get_user(msg.user_uid)?;
             match msg.data {
@@ -0,0 +1,110 @@
+//! Detection of services that stopped printing output
+use crate::settings::Pattern;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use strip_ansi_escapes as ansi_esc;
+
+/// Output activity of a service, shared with its output readers
+pub struct OutputWatchdog {
+    /// Reference point for `last`
+    base: Instant,
+    /// Milliseconds after base of the last output line or run start
+    last: AtomicU64,
+    /// Expected reply to a probe
+    reply: Option<Pattern>,
+    /// Probe sent, waiting for the reply
+    probing: AtomicBool,
+    /// Reply to the current probe received
+    replied: AtomicBool,
+}
+
+impl OutputWatchdog {
+    pub fn new(reply: Option<Pattern>) -> Self {
+        Self {
+            base: Instant::now(),
+            last: AtomicU64::new(0),
+            reply,
+            probing: AtomicBool::new(false),
+            replied: AtomicBool::new(false),
+        }
+    }
+
+    fn stamp(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.base).as_millis() as u64
+    }
+
+    /// Start counting silence anew, for a new run
+    pub fn reset(&self, now: Instant) {
+        self.last.store(self.stamp(now), Ordering::Release);
+        self.probing.store(false, Ordering::Release);
+        self.replied.store(false, Ordering::Release);
+    }
+
+    /// Record an output line, checking it for the probe reply
+    pub fn line(&self, line: &[u8], now: Instant) {
+        self.last.store(self.stamp(now), Ordering::Release);
+        if !self.probing.load(Ordering::Acquire) {
+            return;
+        }
+        if let Some(reply) = self.reply.as_ref() {
+            let stripped = ansi_esc::strip(line).unwrap();
+            let text = String::from_utf8_lossy(&stripped);
+            if reply.is_match(text.trim_end_matches(&['\n', '\r'][..])) {
+                self.replied.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Stamp of the last line, identifying the current silence, and its duration
+    pub fn silence(&self, now: Instant) -> (u64, Duration) {
+        let last = self.last.load(Ordering::Acquire);
+        let silence = self.stamp(now).saturating_sub(last);
+        (last, Duration::from_millis(silence))
+    }
+
+    /// Wait for a reply to a probe
+    pub fn start_probe(&self) {
+        self.replied.store(false, Ordering::Release);
+        self.probing.store(true, Ordering::Release);
+    }
+
+    /// Stop waiting for the probe reply, returns whether it was received
+    pub fn finish_probe(&self) -> bool {
+        self.probing.store(false, Ordering::Release);
+        self.replied.swap(false, Ordering::AcqRel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_silence() {
+        let watchdog = OutputWatchdog::new(None);
+        let start = Instant::now();
+        watchdog.reset(start);
+        let (first, silence) = watchdog.silence(start + Duration::from_secs(5));
+        assert_eq!(Duration::from_secs(5), silence);
+        watchdog.line(b"foo\n", start + Duration::from_secs(6));
+        let (last, silence) = watchdog.silence(start + Duration::from_secs(8));
+        assert_eq!(Duration::from_secs(2), silence);
+        assert_ne!(first, last);
+    }
+
+    #[test]
+    fn test_probe() {
+        let watchdog = OutputWatchdog::new(Some(Pattern(Regex::new("^pong$").unwrap())));
+        let now = Instant::now();
+        // replies outside of a probe don't count
+        watchdog.line(b"pong\n", now);
+        watchdog.start_probe();
+        watchdog.line(b"ping\n", now);
+        assert!(!watchdog.finish_probe());
+        watchdog.start_probe();
+        watchdog.line(b"\x1b[32mpong\x1b[0m\r\n", now);
+        assert!(watchdog.finish_probe());
+        assert!(!watchdog.finish_probe());
+    }
+}
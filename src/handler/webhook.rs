@@ -0,0 +1,384 @@
+//! Outgoing webhooks for service events
+use crate::db::models::{Date, LogAction, SID, UID};
+use crate::messages::unchecked::{ServiceEvent, SetWebhooks};
+use crate::settings::{Webhook, WebhookFormat};
+
+use actix::prelude::*;
+use actix_rt::time::delay_for;
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Header carrying the HMAC-SHA256 of the body as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// Delay before the first retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum doublings of the retry delay
+const MAX_BACKOFF_EXP: u32 = 6;
+
+#[derive(Default)]
+pub struct WebhookService {
+    hooks: Vec<Webhook>,
+    names: HashMap<SID, String>,
+    client: Client,
+}
+
+impl SystemService for WebhookService {}
+impl Supervised for WebhookService {}
+
+impl Actor for WebhookService {
+    type Context = Context<Self>;
+}
+
+impl Handler<SetWebhooks> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetWebhooks, _ctx: &mut Context<Self>) {
+        self.hooks = msg.hooks;
+        self.names = msg.names;
+    }
+}
+
+impl Handler<ServiceEvent> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServiceEvent, _ctx: &mut Context<Self>) {
+        let kind = msg.action.kind();
+        let name = match self.names.get(&msg.id) {
+            Some(v) => v.clone(),
+            None => msg.id.to_string(),
+        };
+        for hook in self.hooks.iter().filter(|h| subscribed(h, msg.id, kind)) {
+            let body = payload(hook.format, &msg, &name);
+            let delivery = Delivery {
+                url: hook.url.clone(),
+                signature: hook.secret.as_ref().map(|s| signature(s, &body)),
+                body,
+                retries: hook.retries,
+                timeout: Duration::from_secs(hook.timeout_secs),
+            };
+            let client = self.client.clone();
+            actix::spawn(async move {
+                deliver(client, delivery, RETRY_BACKOFF).await;
+            });
+        }
+    }
+}
+
+/// Whether the webhook wants events of this kind and service
+fn subscribed(hook: &Webhook, id: SID, kind: &str) -> bool {
+    (hook.services.is_empty() || hook.services.contains(&id))
+        && hook.events.iter().any(|e| e == kind)
+}
+
+/// Human readable description of an event
+fn describe(action: &LogAction) -> String {
+    match action {
+        LogAction::SystemStartup => "loaded on daemon startup".to_owned(),
+        LogAction::ServiceCmdKilled => "kill requested".to_owned(),
+        LogAction::ServiceKilled => "killed".to_owned(),
+        LogAction::ServiceCmdStop => "stop requested".to_owned(),
+        LogAction::ServiceEnded => "ended".to_owned(),
+        LogAction::ServiceStopped => "stopped".to_owned(),
+        LogAction::ServiceStartFailed(e) => format!("failed to start: {}", e),
+        LogAction::ServiceStarted => "started".to_owned(),
+        LogAction::ServiceCmdStart => "start requested".to_owned(),
+        LogAction::ServiceCrashed(code) => format!("crashed with code {}", code),
+        LogAction::Stdin(input) => format!("received input: {}", input),
+        LogAction::StdinEof => "stdin closed".to_owned(),
+        LogAction::StdinDenied(input) => format!("denied input: {}", input),
+        LogAction::OutputTrigger(line) => format!("output trigger: {}", line),
+        LogAction::OutputNotify(line) => format!("output notification: {}", line),
+        LogAction::ServiceLimitExceeded(limit) => format!("crashed: {}", limit),
+        LogAction::ServiceOomKilled => "killed by the OOM killer".to_owned(),
+        LogAction::ServicePaused => "paused".to_owned(),
+        LogAction::ServiceResumed => "resumed".to_owned(),
+        LogAction::Signal(name) => format!("received {}", name),
+        LogAction::ServiceCmdRestart => "restart requested".to_owned(),
+        LogAction::ServiceTimedOut(secs) => format!("timed out after {}s", secs),
+        LogAction::OutputSilent(secs) => format!("printed no output for {}s", secs),
+        LogAction::MemoryRestart(mb) => format!("restarting at {} MB memory usage", mb),
+    }
+}
+
+/// Generic JSON payload
+#[derive(Serialize)]
+struct EventPayload<'a> {
+    service: SID,
+    name: &'a str,
+    event: &'static str,
+    action: &'a LogAction,
+    time: Date,
+    invoker: Option<UID>,
+    message: &'a str,
+}
+
+/// Request body for the format
+fn payload(format: WebhookFormat, event: &ServiceEvent, name: &str) -> String {
+    let message = format!("Service {} {}", name, describe(&event.action));
+    let value = match format {
+        WebhookFormat::Json => json!(EventPayload {
+            service: event.id,
+            name,
+            event: event.action.kind(),
+            action: &event.action,
+            time: event.time,
+            invoker: event.invoker,
+            message: &message,
+        }),
+        WebhookFormat::Slack => json!({ "text": message }),
+        WebhookFormat::Discord => json!({ "content": message }),
+    };
+    value.to_string()
+}
+
+/// Signature header value of the body
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts any key size");
+    mac.input(body.as_bytes());
+    format!("sha256={}", HEXLOWER.encode(&mac.result().code()))
+}
+
+/// Prepared request of one webhook
+struct Delivery {
+    url: String,
+    body: String,
+    signature: Option<String>,
+    retries: u32,
+    timeout: Duration,
+}
+
+/// Send with retries, returns true on a success status
+async fn deliver(client: Client, delivery: Delivery, backoff: Duration) -> bool {
+    let mut attempt = 0;
+    loop {
+        let mut request = client
+            .post(&delivery.url)
+            .timeout(delivery.timeout)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(signature) = delivery.signature.as_ref() {
+            request = request.header(SIGNATURE_HEADER, signature.as_str());
+        }
+        match request.body(delivery.body.clone()).send().await {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) => warn!("Webhook {} returned {}", delivery.url, response.status()),
+            Err(e) => warn!("Webhook {} failed: {}", delivery.url, e),
+        }
+        if attempt >= delivery.retries {
+            error!(
+                "Webhook {} failed after {} attempts, dropping event",
+                delivery.url,
+                attempt + 1
+            );
+            return false;
+        }
+        delay_for(backoff * 2u32.pow(attempt.min(MAX_BACKOFF_EXP))).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use native_tls::{Identity, TlsAcceptor};
+    use reqwest::Certificate;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn hook() -> Webhook {
+        Webhook {
+            url: String::new(),
+            services: vec![1],
+            events: vec!["ServiceCrashed".to_owned()],
+            secret: None,
+            format: WebhookFormat::Json,
+            retries: 0,
+            timeout_secs: 5,
+        }
+    }
+
+    fn crash() -> ServiceEvent {
+        ServiceEvent {
+            id: 1,
+            time: 1000,
+            action: LogAction::ServiceCrashed(3),
+            invoker: None,
+        }
+    }
+
+    /// Read one request and answer with status, returns (head, body)
+    fn answer<S: Read + Write>(stream: &mut S, status: u16) -> (String, String) {
+        let mut data = Vec::new();
+        let mut buf = [0; 1024];
+        let head_end = loop {
+            let read = stream.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..read]);
+            if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&data[..head_end]).to_lowercase();
+        let length: usize = head
+            .lines()
+            .find(|l| l.starts_with("content-length:"))
+            .map(|l| l["content-length:".len()..].trim().parse().unwrap())
+            .unwrap_or(0);
+        while data.len() < head_end + length {
+            let read = stream.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..read]);
+        }
+        let body = String::from_utf8_lossy(&data[head_end..]).into_owned();
+        write!(
+            stream,
+            "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            status
+        )
+        .unwrap();
+        stream.flush().unwrap();
+        (head, body)
+    }
+
+    /// HTTP(S) stand-in answering one request per status, sends back (head, body)
+    fn stand_in(
+        statuses: Vec<u16>,
+        tls: Option<TlsAcceptor>,
+    ) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let url = format!("{}://{}/hook", scheme, listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let request = match tls.as_ref() {
+                    Some(tls) => answer(&mut tls.accept(stream).unwrap(), status),
+                    None => answer(&mut { stream }, status),
+                };
+                tx.send(request).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn test_subscribed() {
+        let hook = hook();
+        assert!(subscribed(&hook, 1, "ServiceCrashed"));
+        assert!(!subscribed(&hook, 2, "ServiceCrashed"));
+        assert!(!subscribed(&hook, 1, "ServiceStarted"));
+        let all = Webhook {
+            services: Vec::new(),
+            ..hook
+        };
+        assert!(subscribed(&all, 2, "ServiceCrashed"));
+    }
+
+    #[test]
+    fn test_payload() {
+        let event = crash();
+        let json: serde_json::Value =
+            serde_json::from_str(&payload(WebhookFormat::Json, &event, "foo")).unwrap();
+        assert_eq!(1, json["service"]);
+        assert_eq!("foo", json["name"]);
+        assert_eq!("ServiceCrashed", json["event"]);
+        assert_eq!(3, json["action"]["ServiceCrashed"]);
+        assert_eq!("Service foo crashed with code 3", json["message"]);
+        assert_eq!(
+            r#"{"text":"Service foo crashed with code 3"}"#,
+            payload(WebhookFormat::Slack, &event, "foo")
+        );
+        assert_eq!(
+            r#"{"content":"Service foo crashed with code 3"}"#,
+            payload(WebhookFormat::Discord, &event, "foo")
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            signature("Jefe", "what do ya want for nothing?")
+        );
+    }
+
+    #[test]
+    fn test_deliver_retry() {
+        let (url, rx) = stand_in(vec![500, 200], None);
+        let delivery = Delivery {
+            url,
+            body: String::from("{}"),
+            signature: Some(signature("key", "{}")),
+            retries: 2,
+            timeout: Duration::from_secs(5),
+        };
+        let delivered = System::new("test").block_on(deliver(
+            Client::default(),
+            delivery,
+            Duration::from_millis(10),
+        ));
+        assert!(delivered);
+        for _ in 0..2 {
+            let (head, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(head.starts_with("post /hook "));
+            assert!(head.contains("content-type: application/json"));
+            assert!(head.contains(&format!("x-signature: {}", signature("key", "{}"))));
+            assert_eq!("{}", body);
+        }
+    }
+
+    #[test]
+    fn test_deliver_https() {
+        let cert = fs::read("tests/tls/cert.pem").unwrap();
+        let key = fs::read("tests/tls/key.pem").unwrap();
+        let acceptor = TlsAcceptor::new(Identity::from_pkcs8(&cert, &key).unwrap()).unwrap();
+        let (url, rx) = stand_in(vec![200], Some(acceptor));
+        let client = Client::builder()
+            .add_root_certificate(Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let delivery = Delivery {
+            url,
+            body: String::from("{}"),
+            signature: None,
+            retries: 0,
+            timeout: Duration::from_secs(5),
+        };
+        let delivered =
+            System::new("test").block_on(deliver(client, delivery, Duration::from_millis(10)));
+        assert!(delivered);
+        let (head, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(head.starts_with("post /hook "));
+        assert_eq!("{}", body);
+    }
+
+    #[test]
+    fn test_deliver_give_up() {
+        let (url, rx) = stand_in(vec![503, 503], None);
+        let delivery = Delivery {
+            url,
+            body: String::from("{}"),
+            signature: None,
+            retries: 1,
+            timeout: Duration::from_secs(5),
+        };
+        let delivered = System::new("test").block_on(deliver(
+            Client::default(),
+            delivery,
+            Duration::from_millis(10),
+        ));
+        assert!(!delivered);
+        for _ in 0..2 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+}
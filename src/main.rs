@@ -6,13 +6,16 @@ extern crate failure;
 extern crate lazy_static;
 
 use crate::handler::messages;
+use crate::handler::mail::MailService;
 use crate::handler::service::ServiceController;
 use crate::handler::user::UserService;
+use crate::handler::webhook::WebhookService;
 
 use actix;
 use actix::prelude::*;
 use failure::Fallible;
 use env_logger;
+use std::collections::HashMap;
 
 mod crypto;
 mod db;
@@ -47,6 +50,16 @@ fn main() -> Fallible<()> {
     // let sigint = Signal::new(SIGINT).flatten_stream();
     // let sigterm = Signal::new(SIGTERM).flatten_stream();
     let services = settings.services;
+    let webhooks = settings.webhooks;
+    let smtp = settings.smtp;
+    let monitoring = settings.monitoring;
+    let cgroup_root = settings.cgroups.map(|v| v.root);
+    let names: HashMap<_, _> = services.iter().map(|s| (s.id, s.name.clone())).collect();
+    // the first instance has the template ID
+    let templates: HashMap<_, _> = services
+        .iter()
+        .filter_map(|s| s.template.filter(|t| *t != s.id).map(|t| (s.id, t)))
+        .collect();
 
     let bcrypt_cost = settings.security.bcrypt_cost;
     let max_session_age_secs = settings.web.max_session_age_secs;
@@ -61,10 +74,30 @@ fn main() -> Fallible<()> {
                     cost: bcrypt_cost,
                     max_session_age_secs,
                     disable_totp,
+                    templates: templates.clone(),
+                })
+                .await?;
+            WebhookService::from_registry()
+                .send(messages::unchecked::SetWebhooks {
+                    hooks: webhooks,
+                    names: names.clone(),
+                })
+                .await?;
+            MailService::from_registry()
+                .send(messages::unchecked::SetSmtp {
+                    smtp,
+                    names,
+                    templates,
                 })
                 .await?;
             ServiceController::from_registry()
-                .send(messages::unchecked::LoadServices { data: services })
+                .send(messages::unchecked::SetMonitoring { monitoring })
+                .await?;
+            ServiceController::from_registry()
+                .send(messages::unchecked::LoadServices {
+                    data: services,
+                    cgroup_root,
+                })
                 .await?;
             UserService::from_registry()
                 .send(messages::unchecked::StartupCheck {})
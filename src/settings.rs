@@ -1,7 +1,13 @@
-use crate::db::models::SID;
+use crate::db::models::{LogAction, SID};
+#[cfg(unix)]
+use crate::handler::signal;
+use chrono::NaiveTime;
 use config::{Config, ConfigError, Environment, File};
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashSet;
+use std::mem;
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 #[derive(Fail, Debug)]
@@ -10,6 +16,35 @@ pub enum SettingsError {
     ParsingError(ConfigError),
     #[fail(display = "The service id '{}' is used multiple times!", _0)]
     IDReuse(SID),
+    #[fail(display = "Stdin trigger of service '{}' requires an input!", _0)]
+    TriggerInput(SID),
+    #[fail(display = "Unknown webhook event '{}'!", _0)]
+    WebhookEvent(String),
+    #[fail(
+        display = "Metrics endpoint requires a token or allowed IPs, unless bound to a loopback IP on an own port!"
+    )]
+    MetricsUnguarded,
+    #[fail(
+        display = "Metrics allow_ips with loopback addresses require an own bind port, clients behind a local proxy would be allowed!"
+    )]
+    MetricsLoopback,
+    #[fail(
+        display = "Service '{}' uses a cgroup, but no cgroups root is set!",
+        _0
+    )]
+    CgroupRoot(SID),
+    #[fail(display = "Service '{}' allows unknown signal '{}'!", _0, _1)]
+    UnknownSignal(SID, String),
+    #[fail(
+        display = "Watchdog probe of service '{}' requires a probe and reply!",
+        _0
+    )]
+    WatchdogProbe(SID),
+    #[fail(
+        display = "Memory restart of service '{}' requires monitoring to be enabled!",
+        _0
+    )]
+    MemoryMonitoring(SID),
 }
 
 impl From<ConfigError> for SettingsError {
@@ -24,6 +59,12 @@ pub struct Settings {
     pub database: Option<Database>,
     pub services: Vec<Service>,
     pub security: Security,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    pub smtp: Option<Smtp>,
+    #[serde(default = "default_monitoring")]
+    pub monitoring: Monitoring,
+    pub cgroups: Option<Cgroups>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +72,60 @@ pub struct Web {
     pub max_session_age_secs: u32,
     pub bind_ip: String,
     pub bind_port: u16,
+    /// Prometheus `/metrics` endpoint, disabled if not set
+    pub metrics: Option<MetricsAccess>,
+}
+
+fn default_metrics_bind_ip() -> String {
+    "127.0.0.1".to_owned()
+}
+
+/// Access to the metrics endpoint, granted by token or client IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsAccess {
+    /// Required as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Clients allowed without token
+    #[serde(default)]
+    pub allow_ips: Vec<IpAddr>,
+    /// Bind IP of the own metrics listener
+    #[serde(default = "default_metrics_bind_ip")]
+    pub bind_ip: String,
+    /// Serve metrics on their own listener instead of the web interface
+    pub bind_port: Option<u16>,
+}
+
+fn default_monitoring_interval() -> u64 {
+    5
+}
+
+fn default_monitoring_history() -> usize {
+    120
+}
+
+fn default_monitoring() -> Monitoring {
+    Monitoring {
+        interval_secs: default_monitoring_interval(),
+        history: default_monitoring_history(),
+    }
+}
+
+/// Resource usage sampling of running services
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Monitoring {
+    /// Seconds between samples, 0 to disable
+    #[serde(default = "default_monitoring_interval")]
+    pub interval_secs: u64,
+    /// Samples to keep per service
+    #[serde(default = "default_monitoring_history")]
+    pub history: usize,
+}
+
+/// cgroup v2 subtree delegated to SD
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cgroups {
+    /// Parent of the service groups, SD must not run in it
+    pub root: PathBuf,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,10 +145,411 @@ fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn default_log_retention() -> usize {
+    7
+}
+
+fn default_stop_timeout() -> u64 {
+    30
+}
+
+fn default_success_exit_codes() -> Vec<i32> {
+    vec![0]
+}
+
+fn default_memory_grace() -> u64 {
+    300
+}
+
+fn default_tty_cols() -> u16 {
+    80
+}
+
+fn default_tty_rows() -> u16 {
+    24
+}
+
+/// Console log file settings of a service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFile {
+    /// Rotate when the file would exceed this size in bytes
+    pub max_size: Option<u64>,
+    /// Rotate on day change
+    #[serde(default)]
+    pub daily: bool,
+    /// Amount of rotated files to keep
+    #[serde(default = "default_log_retention")]
+    pub retention: usize,
+    /// Gzip rotated files
+    #[serde(default = "default_true")]
+    pub compress: bool,
+}
+
+/// Handling of ANSI escape sequences in service output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnsiMode {
+    /// Remove all escape sequences
+    Strip,
+    /// Keep colors as styled spans
+    Styled,
+}
+
+fn default_ansi() -> AnsiMode {
+    AnsiMode::Strip
+}
+
+/// Output rate limit of a service, per second
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputLimit {
+    /// Maximum lines per second
+    pub lines: Option<u32>,
+    /// Maximum bytes per second
+    pub bytes: Option<u64>,
+}
+
+/// Resource limits applied to the service process (unix only)
+///
+/// Soft and hard limit are set to the same value, except for the CPU time.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Rlimits {
+    /// Open file descriptors
+    pub nofile: Option<u64>,
+    /// Processes of the user
+    pub nproc: Option<u64>,
+    /// Virtual memory in bytes
+    pub as_bytes: Option<u64>,
+    /// Data segment in bytes
+    pub data_bytes: Option<u64>,
+    /// CPU time in seconds, killed by SIGXCPU when exceeded
+    pub cpu_secs: Option<u64>,
+    /// Core dump size in bytes, 0 to disable core dumps
+    pub core_bytes: Option<u64>,
+}
+
+/// cgroup v2 limits of a service, unset values mean "max"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CgroupLimits {
+    /// memory.max in bytes
+    pub memory_max: Option<u64>,
+    /// cpu.max as "$MAX $PERIOD" in microseconds
+    pub cpu_max: Option<String>,
+    /// pids.max
+    pub pids_max: Option<u64>,
+}
+
+fn default_hook_timeout() -> u64 {
+    60
+}
+
+/// Command run in the service directory around starts and stops
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hook {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds until the hook is killed and counted as failed
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Regular expression, compiled on config load
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl Pattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(Pattern).map_err(de::Error::custom)
+    }
+}
+
+/// Daily time range in local time, written as "HH:MM-HH:MM", may span midnight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl Serialize for TimeWindow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeWindow {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let window = String::deserialize(deserializer)?;
+        let mut parts = window.splitn(2, '-');
+        let mut time = || -> Result<NaiveTime, D::Error> {
+            let part = parts.next().unwrap_or_default().trim();
+            NaiveTime::parse_from_str(part, "%H:%M")
+                .map_err(|e| de::Error::custom(format!("invalid time window '{}': {}", window, e)))
+        };
+        Ok(TimeWindow {
+            start: time()?,
+            end: time()?,
+        })
+    }
+}
+
+/// Stdin access of a service for users
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdinPolicy {
+    /// No user input at all
+    Disabled,
+    /// Any input not matching `stdin_deny`
+    Enabled,
+    /// Only input matching `stdin_allow` and not `stdin_deny`
+    Restricted,
+}
+
+fn default_stdin() -> StdinPolicy {
+    StdinPolicy::Enabled
+}
+
+/// Output stream of an output trigger
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerStream {
+    Stdout,
+    Stderr,
+    Any,
+}
+
+fn default_trigger_stream() -> TriggerStream {
+    TriggerStream::Any
+}
+
+/// Action of an output trigger
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerAction {
+    /// Log entry with the matching line
+    Log,
+    /// Log entry with console snapshot
+    Snapshot,
+    /// Kill and start the service
+    Restart,
+    /// Soft-stop, kill if no soft_stop is set
+    Stop,
+    /// Send `input` as line to stdin
+    Stdin,
+    /// Log entry for notifications
+    Notify,
+}
+
+fn default_trigger_cooldown() -> u64 {
+    60
+}
+
+/// Action on output lines matching a pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trigger {
+    pub pattern: Pattern,
+    #[serde(default = "default_trigger_stream")]
+    pub stream: TriggerStream,
+    pub action: TriggerAction,
+    /// Input for the stdin action
+    pub input: Option<String>,
+    /// Minimum seconds between firing
+    #[serde(default = "default_trigger_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+/// Action when a service prints no output for `output_watchdog_secs`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchdogAction {
+    /// Log entry
+    Log,
+    /// Log entry with console snapshot
+    Snapshot,
+    /// Stop and start the service
+    Restart,
+    /// Send `output_watchdog_probe`, restart without reply
+    Probe,
+}
+
+fn default_watchdog_action() -> WatchdogAction {
+    WatchdogAction::Log
+}
+
+fn default_watchdog_reply_secs() -> u64 {
+    10
+}
+
+/// Connection security for SMTP
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection, for local relays only
+    None,
+    /// Upgrade via STARTTLS, fail if unsupported
+    Starttls,
+    /// TLS from connection start
+    Tls,
+}
+
+fn default_smtp_security() -> SmtpSecurity {
+    SmtpSecurity::Starttls
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_mail_retries() -> u32 {
+    3
+}
+
+fn default_smtp_timeout() -> u64 {
+    30
+}
+
+fn default_mail_console_lines() -> usize {
+    20
+}
+
+fn default_restart_loop_crashes() -> usize {
+    3
+}
+
+fn default_restart_loop_secs() -> u64 {
+    300
+}
+
+/// Mail server for notifications
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Smtp {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default = "default_smtp_security")]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address
+    pub from: String,
+    /// Retries after a failed delivery, with exponential backoff
+    #[serde(default = "default_mail_retries")]
+    pub retries: u32,
+    /// Connection timeout in seconds
+    #[serde(default = "default_smtp_timeout")]
+    pub timeout_secs: u64,
+    /// Last console lines of the snapshot to include
+    #[serde(default = "default_mail_console_lines")]
+    pub console_lines: usize,
+    /// Amount of crashes within `restart_loop_secs` considered a restart loop
+    #[serde(default = "default_restart_loop_crashes")]
+    pub restart_loop_crashes: usize,
+    #[serde(default = "default_restart_loop_secs")]
+    pub restart_loop_secs: u64,
+}
+
+/// Payload format of a webhook
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// Generic JSON with event details
+    Json,
+    /// Slack incoming webhook
+    Slack,
+    /// Discord webhook
+    Discord,
+}
+
+fn default_webhook_format() -> WebhookFormat {
+    WebhookFormat::Json
+}
+
+fn default_webhook_events() -> Vec<String> {
+    [
+        "ServiceStarted",
+        "ServiceStartFailed",
+        "ServiceEnded",
+        "ServiceCrashed",
+        "ServiceLimitExceeded",
+        "ServiceOomKilled",
+        "ServiceTimedOut",
+        "ServiceStopped",
+        "ServiceKilled",
+    ]
+    .iter()
+    .map(|v| (*v).to_owned())
+    .collect()
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+/// Outgoing HTTP notification on service events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Target URL, http or https
+    pub url: String,
+    /// Services to notify about, all if empty
+    #[serde(default)]
+    pub services: Vec<SID>,
+    /// Log action kinds to notify about
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<String>,
+    /// Key for the HMAC-SHA256 `X-Signature` header
+    pub secret: Option<String>,
+    #[serde(default = "default_webhook_format")]
+    pub format: WebhookFormat,
+    /// Retries after a failed delivery, with exponential backoff
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// Request timeout in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Placeholder replaced by the instance index of templates
+const INSTANCE_PLACEHOLDER: &str = "{instance}";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     pub id: SID,
     pub name: String,
+    /// Run as template for this amount of instances, with IDs counting up from `id`
+    pub instances: Option<u32>,
+    /// Template ID of instances, set on config load
+    #[serde(skip)]
+    pub template: Option<SID>,
     #[serde(default)]
     pub restart: bool,
     #[serde(default)]
@@ -66,8 +562,20 @@ pub struct Service {
     #[serde(default)]
     pub args: Vec<String>,
     pub soft_stop: Option<String>,
+    /// Seconds to wait after soft-stop before killing on restarts
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout_secs: u64,
     #[serde(default)]
     pub restart_always: bool,
+    /// Exit codes counted as normal end instead of crash
+    #[serde(default = "default_success_exit_codes")]
+    pub success_exit_codes: Vec<i32>,
+    /// Exit codes to always restart on
+    #[serde(default)]
+    pub restart_exit_codes: Vec<i32>,
+    /// Exit codes to never restart on, takes precedence
+    #[serde(default)]
+    pub no_restart_exit_codes: Vec<i32>,
     #[serde(default)]
     pub snapshot_console_on_stop: bool,
     #[serde(default = "default_true")]
@@ -76,6 +584,130 @@ pub struct Service {
     pub snapshot_console_on_manual_stop: bool,
     #[serde(default)]
     pub snapshot_console_on_manual_kill: bool,
+    /// Stop the service after running this long
+    pub max_runtime_secs: Option<u64>,
+    #[serde(default = "default_true")]
+    pub snapshot_console_on_timeout: bool,
+    /// Run attached to a pseudo terminal instead of pipes (unix only)
+    #[serde(default)]
+    pub tty: bool,
+    /// Terminal width for tty mode
+    #[serde(default = "default_tty_cols")]
+    pub tty_cols: u16,
+    /// Terminal height for tty mode
+    #[serde(default = "default_tty_rows")]
+    pub tty_rows: u16,
+    /// User stdin policy
+    #[serde(default = "default_stdin")]
+    pub stdin: StdinPolicy,
+    /// Input patterns allowed in restricted mode
+    #[serde(default)]
+    pub stdin_allow: Vec<Pattern>,
+    /// Input patterns always denied
+    #[serde(default)]
+    pub stdin_deny: Vec<Pattern>,
+    /// Input patterns to redact in logs and console, masks only the first group if present
+    #[serde(default)]
+    pub stdin_mask: Vec<Pattern>,
+    /// ANSI escape sequence handling of output
+    #[serde(default = "default_ansi")]
+    pub ansi: AnsiMode,
+    /// Seconds without any output line until the watchdog action runs
+    pub output_watchdog_secs: Option<u64>,
+    #[serde(default = "default_watchdog_action")]
+    pub output_watchdog_action: WatchdogAction,
+    /// Stdin line sent by the probe action
+    pub output_watchdog_probe: Option<String>,
+    /// Expected output line on the probe
+    pub output_watchdog_reply: Option<Pattern>,
+    /// Seconds to wait for the probe reply
+    #[serde(default = "default_watchdog_reply_secs")]
+    pub output_watchdog_reply_secs: u64,
+    /// Restart when using more memory (RSS) for `memory_restart_grace_secs`
+    pub restart_on_memory_mb: Option<u64>,
+    #[serde(default = "default_memory_grace")]
+    pub memory_restart_grace_secs: u64,
+    /// Only restart within this daily time window
+    pub memory_restart_window: Option<TimeWindow>,
+    /// Signals users can send, such as "SIGHUP" (unix only)
+    #[serde(default)]
+    pub signals: Vec<String>,
+    /// Write console output to logs/<sid>-<name>.log
+    pub log_file: Option<LogFile>,
+    /// Drop output exceeding this rate
+    pub output_limit: Option<OutputLimit>,
+    /// Actions on matching output
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    /// Resource limits of the process
+    pub rlimits: Option<Rlimits>,
+    /// Run in its own cgroup below the cgroups root (linux only)
+    pub cgroup: Option<CgroupLimits>,
+    /// Run before starting, a failure aborts the start
+    pub pre_start: Option<Hook>,
+    /// Run after the process started
+    pub post_start: Option<Hook>,
+    /// Run before soft-stopping, not on kills
+    pub pre_stop: Option<Hook>,
+    /// Run after the process ended, before restarts
+    pub post_stop: Option<Hook>,
+}
+
+impl Service {
+    /// Index of template instances
+    pub fn instance(&self) -> Option<u32> {
+        self.template.map(|template| self.id - template)
+    }
+
+    /// Instance of this template, `{instance}` is replaced by the index
+    ///
+    /// The name gets the index appended if it has no placeholder.
+    fn instance_of(&self, index: u32) -> Service {
+        let replace = |v: &String| v.replace(INSTANCE_PLACEHOLDER, &index.to_string());
+        let replace_hook = |hook: &Option<Hook>| {
+            hook.clone().map(|hook| Hook {
+                args: hook.args.iter().map(replace).collect(),
+                ..hook
+            })
+        };
+        let name = if self.name.contains(INSTANCE_PLACEHOLDER) {
+            replace(&self.name)
+        } else {
+            format!("{}-{}", self.name, index)
+        };
+        Service {
+            id: self.id.saturating_add(index),
+            name,
+            instances: None,
+            template: Some(self.id),
+            args: self.args.iter().map(replace).collect(),
+            soft_stop: self.soft_stop.as_ref().map(replace),
+            pre_start: replace_hook(&self.pre_start),
+            post_start: replace_hook(&self.post_start),
+            pre_stop: replace_hook(&self.pre_stop),
+            post_stop: replace_hook(&self.post_stop),
+            ..self.clone()
+        }
+    }
+
+    /// Whether to restart after the process exited by itself
+    ///
+    /// `code` is None if the process was terminated by a signal.
+    pub fn restart_on_exit(&self, code: Option<i32>, crashed: bool) -> bool {
+        if let Some(code) = code {
+            if self.no_restart_exit_codes.contains(&code) {
+                return false;
+            }
+            if self.restart_exit_codes.contains(&code) {
+                return true;
+            }
+        }
+        if crashed {
+            self.restart
+        } else {
+            self.restart_always
+        }
+    }
 }
 
 impl Settings {
@@ -92,17 +724,78 @@ impl Settings {
         }
         let mut config: Self = s.try_into()?;
 
+        config.expand_instances();
         config.validate()?;
 
         config.services.retain(|s| s.enabled);
         Ok(config)
     }
+    /// Replace templates by their instances
+    fn expand_instances(&mut self) {
+        let services = mem::take(&mut self.services);
+        for service in services {
+            match service.instances {
+                Some(amount) => self
+                    .services
+                    .extend((0..amount).map(|i| service.instance_of(i))),
+                None => self.services.push(service),
+            }
+        }
+    }
     fn validate(&self) -> Result<(), SettingsError> {
         let mut ids = HashSet::new();
         for service in self.services.iter() {
             if !ids.insert(service.id) {
                 return Err(SettingsError::IDReuse(service.id));
             }
+            if service.cgroup.is_some() && self.cgroups.is_none() {
+                return Err(SettingsError::CgroupRoot(service.id));
+            }
+            #[cfg(unix)]
+            for name in service.signals.iter() {
+                if signal::lookup(name).is_none() {
+                    return Err(SettingsError::UnknownSignal(service.id, name.clone()));
+                }
+            }
+            if service.restart_on_memory_mb.is_some() && self.monitoring.interval_secs == 0 {
+                return Err(SettingsError::MemoryMonitoring(service.id));
+            }
+            if service.output_watchdog_action == WatchdogAction::Probe
+                && (service.output_watchdog_probe.is_none()
+                    || service.output_watchdog_reply.is_none())
+            {
+                return Err(SettingsError::WatchdogProbe(service.id));
+            }
+            for trigger in service.triggers.iter() {
+                if trigger.action == TriggerAction::Stdin && trigger.input.is_none() {
+                    return Err(SettingsError::TriggerInput(service.id));
+                }
+            }
+        }
+        if let Some(metrics) = self.web.metrics.as_ref() {
+            // an own listener is only private on loopback
+            let local = metrics.bind_port.is_some()
+                && metrics
+                    .bind_ip
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.is_loopback())
+                    .unwrap_or(false);
+            if metrics.token.is_none() && metrics.allow_ips.is_empty() && !local {
+                return Err(SettingsError::MetricsUnguarded);
+            }
+            // the web interface is usually behind a reverse proxy on the same host
+            if metrics.bind_port.is_none() && metrics.allow_ips.iter().any(|ip| ip.is_loopback()) {
+                return Err(SettingsError::MetricsLoopback);
+            }
+        }
+        for hook in self.webhooks.iter() {
+            if let Some(event) = hook
+                .events
+                .iter()
+                .find(|e| !LogAction::KINDS.contains(&e.as_str()))
+            {
+                return Err(SettingsError::WebhookEvent(event.clone()));
+            }
         }
         Ok(())
     }
@@ -123,6 +816,149 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_instances() {
+        let settings = Settings::new_opt(Some("tests/instances.toml")).unwrap();
+        let ids: Vec<_> = settings.services.iter().map(|s| s.id).collect();
+        assert_eq!(vec![10, 11, 12, 20], ids);
+        let worker = &settings.services[2];
+        assert_eq!("worker-2", worker.name);
+        assert_eq!(vec!["--port=802".to_owned()], worker.args);
+        assert_eq!(Some("stop 2"), worker.soft_stop.as_deref());
+        assert_eq!(Some(10), worker.template);
+        assert_eq!(Some(2), worker.instance());
+        // placeholders of normal services are kept
+        let web = &settings.services[3];
+        assert_eq!("web {instance}", web.name);
+        assert_eq!(None, web.instance());
+
+        match Settings::new_opt(Some("tests/instances_overlap.toml")) {
+            Err(SettingsError::IDReuse(id)) => assert_eq!(12, id),
+            v => panic!("Expected IDReuse error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_trigger_input() {
+        match Settings::new_opt(Some("tests/trigger_input.toml")) {
+            Err(SettingsError::TriggerInput(id)) => assert_eq!(0, id),
+            v => panic!("Expected TriggerInput error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_webhook_event() {
+        match Settings::new_opt(Some("tests/webhook_event.toml")) {
+            Err(SettingsError::WebhookEvent(event)) => assert_eq!("ServiceExploded", event),
+            v => panic!("Expected WebhookEvent error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_metrics_unguarded() {
+        match Settings::new_opt(Some("tests/metrics_unguarded.toml")) {
+            Err(SettingsError::MetricsUnguarded) => (),
+            v => panic!("Expected MetricsUnguarded error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_metrics_public() {
+        match Settings::new_opt(Some("tests/metrics_public.toml")) {
+            Err(SettingsError::MetricsUnguarded) => (),
+            v => panic!("Expected MetricsUnguarded error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_metrics_loopback() {
+        match Settings::new_opt(Some("tests/metrics_loopback.toml")) {
+            Err(SettingsError::MetricsLoopback) => (),
+            v => panic!("Expected MetricsLoopback error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_cgroup_root() {
+        match Settings::new_opt(Some("tests/cgroup_root.toml")) {
+            Err(SettingsError::CgroupRoot(id)) => assert_eq!(0, id),
+            v => panic!("Expected CgroupRoot error got {:?}", v),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unknown_signal() {
+        match Settings::new_opt(Some("tests/unknown_signal.toml")) {
+            Err(SettingsError::UnknownSignal(id, name)) => {
+                assert_eq!(0, id);
+                assert_eq!("SIGKILL", name);
+            }
+            v => panic!("Expected UnknownSignal error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_watchdog_probe() {
+        match Settings::new_opt(Some("tests/watchdog_probe.toml")) {
+            Err(SettingsError::WatchdogProbe(id)) => assert_eq!(0, id),
+            v => panic!("Expected WatchdogProbe error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_memory_monitoring() {
+        match Settings::new_opt(Some("tests/memory_monitoring.toml")) {
+            Err(SettingsError::MemoryMonitoring(id)) => assert_eq!(0, id),
+            v => panic!("Expected MemoryMonitoring error got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_time_window() {
+        #[derive(Deserialize)]
+        struct Window {
+            w: TimeWindow,
+        }
+        let window = toml::from_str::<Window>("w = \"23:30-01:00\"").unwrap().w;
+        let time = |h, m| NaiveTime::from_hms(h, m, 0);
+        assert!(window.contains(time(23, 45)));
+        assert!(window.contains(time(0, 30)));
+        assert!(!window.contains(time(1, 0)));
+        assert!(!window.contains(time(12, 0)));
+        let window = TimeWindow {
+            start: time(3, 0),
+            end: time(5, 0),
+        };
+        assert!(window.contains(time(4, 59)));
+        assert!(!window.contains(time(2, 59)));
+        assert!(toml::from_str::<Window>("w = \"3-5\"").is_err());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let service: Service = toml::from_str(
+            r#"
+            id = 0
+            name = "java"
+            enabled = true
+            command = "java"
+            directory = "/"
+            restart = true
+            success_exit_codes = [0, 143]
+            restart_exit_codes = [2]
+            no_restart_exit_codes = [78]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(vec![0, 143], service.success_exit_codes);
+        assert!(service.restart_on_exit(Some(2), false));
+        assert!(!service.restart_on_exit(Some(0), false));
+        assert!(service.restart_on_exit(Some(1), true));
+        assert!(!service.restart_on_exit(Some(78), true));
+        assert!(service.restart_on_exit(None, true));
+    }
+
     #[test]
     #[ignore]
     fn test_new() {
@@ -148,10 +984,47 @@ mod tests {
                 max_session_age_secs: 60,
                 bind_ip: String::from("127.0.0.1"),
                 bind_port: 9000,
+                metrics: Some(MetricsAccess {
+                    token: Some("secret token".to_owned()),
+                    allow_ips: vec!["127.0.0.1".parse().unwrap()],
+                    bind_ip: "127.0.0.1".to_owned(),
+                    bind_port: Some(9100),
+                }),
             },
+            webhooks: vec![Webhook {
+                url: "http://127.0.0.1:8080/hook".to_owned(),
+                services: vec![1],
+                events: default_webhook_events(),
+                secret: Some("secret".to_owned()),
+                format: WebhookFormat::Discord,
+                retries: 3,
+                timeout_secs: 10,
+            }],
+            smtp: Some(Smtp {
+                host: "mail.example.com".to_owned(),
+                port: 587,
+                security: SmtpSecurity::Starttls,
+                username: Some("user".to_owned()),
+                password: Some("password".to_owned()),
+                from: "service-daemon@example.com".to_owned(),
+                retries: 3,
+                timeout_secs: 30,
+                console_lines: 20,
+                restart_loop_crashes: 3,
+                restart_loop_secs: 300,
+            }),
+            monitoring: Monitoring {
+                interval_secs: 5,
+                history: 120,
+            },
+            cgroups: Some(Cgroups {
+                root: "/sys/fs/cgroup/service-daemon".into(),
+            }),
             services: vec![
                 Service {
                     name: "some service".to_owned(),
+                    instances: None,
+                    template: None,
                     autostart: true,
                     restart_always: false,
                     enabled: false,
@@ -159,16 +1032,50 @@ mod tests {
                     command: "some cmd".to_owned(),
                     directory: "./foo".into(),
                     soft_stop: None,
+                    stop_timeout_secs: 30,
+                    success_exit_codes: vec![0],
+                    restart_exit_codes: Vec::new(),
+                    no_restart_exit_codes: Vec::new(),
                     args: Vec::new(),
                     snapshot_console_on_stop: true,
                     snapshot_console_on_crash: true,
                     snapshot_console_on_manual_stop: true,
                     snapshot_console_on_manual_kill: true,
+                    max_runtime_secs: None,
+                    snapshot_console_on_timeout: true,
                     id: 0,
                     restart: true,
+                    tty: false,
+                    tty_cols: 80,
+                    tty_rows: 24,
+                    ansi: AnsiMode::Strip,
+                    stdin: StdinPolicy::Enabled,
+                    stdin_allow: Vec::new(),
+                    stdin_deny: Vec::new(),
+                    stdin_mask: Vec::new(),
+                    log_file: None,
+                    output_limit: None,
+                    triggers: Vec::new(),
+                    rlimits: None,
+                    cgroup: None,
+                    pre_start: None,
+                    post_start: None,
+                    pre_stop: None,
+                    post_stop: None,
+                    output_watchdog_secs: None,
+                    output_watchdog_action: WatchdogAction::Log,
+                    output_watchdog_probe: None,
+                    output_watchdog_reply: None,
+                    output_watchdog_reply_secs: 10,
+                    restart_on_memory_mb: None,
+                    memory_restart_grace_secs: 300,
+                    memory_restart_window: None,
+                    signals: Vec::new(),
                 },
                 Service {
                     name: "some service2".to_owned(),
+                    instances: Some(2),
+                    template: None,
                     autostart: false,
                     enabled: false,
                     restart_always: true,
@@ -179,10 +1086,76 @@ mod tests {
                     snapshot_console_on_crash: true,
                     snapshot_console_on_manual_stop: true,
                     snapshot_console_on_manual_kill: true,
+                    max_runtime_secs: Some(3600),
+                    snapshot_console_on_timeout: true,
                     soft_stop: Some("asdf".to_owned()),
+                    stop_timeout_secs: 60,
+                    success_exit_codes: vec![0, 143],
+                    restart_exit_codes: vec![2],
+                    no_restart_exit_codes: vec![78],
                     args: vec!["asd".to_owned(), "def".to_owned()],
                     id: 1,
                     restart: true,
+                    tty: true,
+                    tty_cols: 120,
+                    tty_rows: 40,
+                    ansi: AnsiMode::Styled,
+                    stdin: StdinPolicy::Restricted,
+                    stdin_allow: vec![Pattern(Regex::new("^say .*$").unwrap())],
+                    stdin_deny: vec![Pattern(Regex::new("^stop").unwrap())],
+                    stdin_mask: vec![Pattern(Regex::new("^login \\S+ (.*)$").unwrap())],
+                    log_file: Some(LogFile {
+                        max_size: Some(1024 * 1024),
+                        daily: true,
+                        retention: 7,
+                        compress: true,
+                    }),
+                    output_limit: Some(OutputLimit {
+                        lines: Some(1000),
+                        bytes: None,
+                    }),
+                    triggers: vec![Trigger {
+                        pattern: Pattern(Regex::new("OutOfMemoryError").unwrap()),
+                        stream: TriggerStream::Stderr,
+                        action: TriggerAction::Restart,
+                        input: None,
+                        cooldown_secs: 60,
+                    }],
+                    rlimits: Some(Rlimits {
+                        nofile: Some(1024),
+                        cpu_secs: Some(3600),
+                        core_bytes: Some(0),
+                        ..Default::default()
+                    }),
+                    cgroup: Some(CgroupLimits {
+                        memory_max: Some(2 * 1024 * 1024 * 1024),
+                        cpu_max: Some("200000 100000".to_owned()),
+                        pids_max: Some(256),
+                    }),
+                    pre_start: Some(Hook {
+                        command: "./migrate.sh".to_owned(),
+                        args: Vec::new(),
+                        timeout_secs: 300,
+                    }),
+                    post_start: None,
+                    pre_stop: None,
+                    post_stop: Some(Hook {
+                        command: "./backup.sh".to_owned(),
+                        args: vec!["world".to_owned()],
+                        timeout_secs: 600,
+                    }),
+                    output_watchdog_secs: Some(300),
+                    output_watchdog_action: WatchdogAction::Probe,
+                    output_watchdog_probe: Some("list".to_owned()),
+                    output_watchdog_reply: Some(Pattern(Regex::new("players online").unwrap())),
+                    output_watchdog_reply_secs: 10,
+                    restart_on_memory_mb: Some(4096),
+                    memory_restart_grace_secs: 600,
+                    memory_restart_window: Some(TimeWindow {
+                        start: NaiveTime::from_hms(3, 0, 0),
+                        end: NaiveTime::from_hms(5, 0, 0),
+                    }),
+                    signals: vec!["SIGHUP".to_owned(), "SIGUSR1".to_owned()],
                 },
             ],
         };
@@ -1,12 +1,15 @@
-use crate::db::models::ServicePerm;
+use crate::db::models::{ConsoleOutput, NotifyEvents, ServicePerm};
 use crate::handler::error::UserError;
+use crate::handler::search::{self, SearchQuery};
 use crate::handler::service::ServiceController;
+use crate::handler::stdin::{self, StdinMsg};
 use crate::handler::user::UserService;
 use crate::messages::*;
 use crate::web::models::*;
 use actix::prelude::*;
 use actix_files as fs;
 use actix_identity::*;
+use actix_web::http::header::{ContentDisposition, DispositionType};
 use actix_web::{error::ResponseError, web, Error, HttpRequest, HttpResponse};
 use nanoid::nanoid;
 
@@ -290,6 +293,59 @@ pub async fn session_permissions(id: Identity) -> Result<HttpResponse, Error> {
         })
 }
 
+/// Resource usage history of service
+pub async fn usage(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
+    assert_perm!(id.identity(), item.service, ServicePerm::STATS);
+    ServiceController::from_registry()
+        .send(unchecked::GetUsageHistory { id: item.service })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(v) => HttpResponse::Ok().json(v),
+            Err(e) => e.error_response(),
+        })
+}
+
+/// Mail notification subscription of current session
+pub async fn get_notifications(
+    item: web::Path<ServiceRequest>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let uid = assert_perm!(id.identity(), item.service, ServicePerm::LOG);
+    UserService::from_registry()
+        .send(unchecked::GetNotifications {
+            user: uid,
+            service: item.service,
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(v) => HttpResponse::Ok().json(NotifyEventsWrap { events: v.bits() }),
+            Err(e) => e.error_response(),
+        })
+}
+
+/// Set mail notification subscription of current session
+pub async fn set_notifications(
+    item: web::Path<ServiceRequest>,
+    data: web::Json<NotifyEventsWrap>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let uid = assert_perm!(id.identity(), item.service, ServicePerm::LOG);
+    UserService::from_registry()
+        .send(unchecked::SetNotifications {
+            user: uid,
+            service: item.service,
+            events: NotifyEvents::from_bits_truncate(data.into_inner().events),
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => e.error_response(),
+        })
+}
+
 pub async fn log_latest(
     item: web::Path<LogLatestRequest>,
     id: Identity,
@@ -359,6 +415,77 @@ pub async fn log_console(item: web::Path<LogRequest>, id: Identity) -> Result<Ht
         })
 }
 
+pub async fn log_console_search(
+    item: web::Path<LogRequest>,
+    data: web::Json<SearchQuery>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let item = item.into_inner();
+    assert_perm!(
+        id.identity(),
+        item.service,
+        ServicePerm::OUTPUT | ServicePerm::LOG
+    );
+    let output = ServiceController::from_registry()
+        .send(unchecked::GetLogConsole {
+            id: item.service,
+            log_id: item.log_id,
+        })
+        .await?;
+    Ok(match output {
+        Ok(v) => search_response(&v, &data),
+        Err(e) => e.error_response(),
+    })
+}
+
+/// Search console output, bad request on invalid queries
+fn search_response(output: &ConsoleOutput, query: &SearchQuery) -> HttpResponse {
+    match search::search(output, query) {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::BadRequest().json(e.to_string()),
+    }
+}
+
+pub async fn log_files(
+    item: web::Path<ServiceRequest>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    assert_perm!(id.identity(), service, ServicePerm::LOG);
+    ServiceController::from_registry()
+        .send(unchecked::GetLogFiles { id: service })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(v) => HttpResponse::Ok().json(v),
+            Err(e) => e.error_response(),
+        })
+}
+
+pub async fn log_file(
+    item: web::Path<LogFileRequest>,
+    id: Identity,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let item = item.into_inner();
+    assert_perm!(id.identity(), item.service, ServicePerm::LOG);
+    let res = ServiceController::from_registry()
+        .send(unchecked::GetLogFile {
+            id: item.service,
+            file: item.file,
+        })
+        .await?;
+    match res {
+        Ok(path) => fs::NamedFile::open(path)?
+            .set_content_disposition(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![],
+            })
+            .into_response(&req),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
 // TODO: rewrite to also use service macro
 // currently using manual perm fetching for perms.is_empty()
 pub async fn state(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
@@ -388,15 +515,35 @@ pub async fn state(item: web::Path<ServiceRequest>, id: Identity) -> Result<Http
 
 pub async fn input(
     item: web::Path<ServiceRequest>,
-    data: web::Json<String>,
+    data: web::Json<StdinRequest>,
     id: Identity,
 ) -> Result<HttpResponse, Error> {
     let service = item.into_inner().service;
     let uid = assert_perm!(id.identity(), service, ServicePerm::STDIN_ALL);
+    let (input, sensitive) = match data.into_inner() {
+        StdinRequest::Line(input) => (StdinMsg::line(&input), false),
+        StdinRequest::Input {
+            input,
+            mode,
+            sensitive,
+        } => (
+            match mode {
+                StdinMode::Line => StdinMsg::line(&input),
+                StdinMode::Raw => StdinMsg::Data(input.into_bytes()),
+                StdinMode::Escaped => match stdin::unescape(&input) {
+                    Ok(v) => StdinMsg::Data(v),
+                    Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+                },
+                StdinMode::Eof => StdinMsg::Close,
+            },
+            sensitive,
+        ),
+    };
     ServiceController::from_registry()
         .send(unchecked::SendStdin {
             id: service,
-            input: data.into_inner(),
+            input,
+            sensitive,
             user: Some(uid),
         })
         .await
@@ -439,6 +586,88 @@ pub async fn kill(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpR
         })
 }
 
+pub async fn restart(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    let uid = assert_perm!(id.identity(), service, ServicePerm::RESTART);
+    ServiceController::from_registry()
+        .send(unchecked::RestartService {
+            id: service,
+            user: Some(uid),
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(e) => e.error_response(),
+        })
+}
+
+pub async fn pause(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    let uid = assert_perm!(id.identity(), service, ServicePerm::PAUSE);
+    ServiceController::from_registry()
+        .send(unchecked::PauseService {
+            id: service,
+            user: Some(uid),
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(e) => e.error_response(),
+        })
+}
+
+pub async fn resume(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    let uid = assert_perm!(id.identity(), service, ServicePerm::PAUSE);
+    ServiceController::from_registry()
+        .send(unchecked::ResumeService {
+            id: service,
+            user: Some(uid),
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(e) => e.error_response(),
+        })
+}
+
+/// Allowed signals of service
+pub async fn signals(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
+    assert_perm!(id.identity(), item.service, ServicePerm::SIGNAL);
+    ServiceController::from_registry()
+        .send(unchecked::GetSignals { id: item.service })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(v) => HttpResponse::Ok().json(v),
+            Err(e) => e.error_response(),
+        })
+}
+
+pub async fn signal(
+    item: web::Path<ServiceRequest>,
+    data: web::Json<SignalRequest>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    let uid = assert_perm!(id.identity(), service, ServicePerm::SIGNAL);
+    ServiceController::from_registry()
+        .send(unchecked::SendSignal {
+            id: service,
+            signal: data.into_inner().signal,
+            user: Some(uid),
+        })
+        .await
+        .map_err(Error::from)
+        .map(|response| match response {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(e) => e.error_response(),
+        })
+}
+
 pub async fn stop(item: web::Path<ServiceRequest>, id: Identity) -> Result<HttpResponse, Error> {
     let service = item.into_inner().service;
     let uid = assert_perm!(id.identity(), service, ServicePerm::STOP);
@@ -562,6 +791,22 @@ pub async fn output(item: web::Path<ServiceRequest>, id: Identity) -> Result<Htt
         })
 }
 
+pub async fn output_search(
+    item: web::Path<ServiceRequest>,
+    data: web::Json<SearchQuery>,
+    id: Identity,
+) -> Result<HttpResponse, Error> {
+    let service = item.into_inner().service;
+    assert_perm!(id.identity(), service, ServicePerm::OUTPUT);
+    let output = ServiceController::from_registry()
+        .send(unchecked::GetOutput { id: service })
+        .await?;
+    Ok(match output {
+        Ok(v) => search_response(&v, &data),
+        Err(e) => e.error_response(),
+    })
+}
+
 pub async fn services(id: Identity) -> Result<HttpResponse, Error> {
     let session = get_session_async!(id);
     ServiceController::from_registry()
@@ -0,0 +1,265 @@
+//! Prometheus metrics endpoint
+use crate::handler::service::{ServiceController, State};
+use crate::messages::unchecked::GetMetrics;
+use crate::messages::{DaemonMetrics, ServiceMetrics};
+use crate::settings::MetricsAccess;
+use actix::prelude::*;
+use actix_web::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::{error::ResponseError, web, Error, HttpRequest, HttpResponse};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4";
+
+/// Handled HTTP requests by status class, 1xx to 5xx
+static REQUESTS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Count a handled request
+pub fn count_request(status: StatusCode) {
+    let class = (status.as_u16() / 100) as usize;
+    if let Some(counter) = class.checked_sub(1).and_then(|i| REQUESTS.get(i)) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn requests() -> [u64; 5] {
+    let mut counts = [0; 5];
+    for (count, counter) in counts.iter_mut().zip(REQUESTS.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+    counts
+}
+
+/// Whether the client may read metrics
+///
+/// Allowed IPs are checked first, otherwise the bearer token has to match.
+fn allowed(access: &MetricsAccess, peer: Option<IpAddr>, bearer: Option<&str>) -> bool {
+    if peer.filter(|ip| access.allow_ips.contains(ip)).is_some() {
+        return true;
+    }
+    match (access.token.as_ref(), bearer) {
+        (Some(token), Some(bearer)) => constant_eq(token.as_bytes(), bearer.as_bytes()),
+        _ => false,
+    }
+}
+
+/// Comparison without early return on the first difference
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Escape a label value
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Name, type, help and value of a per service metric
+type ServiceMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&ServiceMetrics) -> i64,
+);
+
+/// Render metrics in the Prometheus text format
+fn render(metrics: &DaemonMetrics, requests: [u64; 5]) -> String {
+    let mut out = String::new();
+    let service_metrics: [ServiceMetric; 9] = [
+        (
+            "sd_service_state",
+            "gauge",
+            "Service state: 0 stopped, 1 running, 2 ended, 3 crashed, 4 stopping, 5 killed, 6 paused, 7 timed out",
+            |s| s.state as i64,
+        ),
+        (
+            "sd_service_up",
+            "gauge",
+            "Whether the service is running",
+            |s| (s.state == State::Running) as i64,
+        ),
+        (
+            "sd_service_uptime_seconds",
+            "gauge",
+            "Seconds since the service was started",
+            |s| s.uptime as i64,
+        ),
+        (
+            "sd_service_restarts_total",
+            "counter",
+            "Automatic restarts since daemon start",
+            |s| s.restarts as i64,
+        ),
+        (
+            "sd_service_crashes_total",
+            "counter",
+            "Crashes since daemon start",
+            |s| s.crashes as i64,
+        ),
+        (
+            "sd_service_last_exit_code",
+            "gauge",
+            "Exit code of the last crash",
+            |s| i64::from(s.last_exit_code),
+        ),
+        (
+            "sd_service_console_lines_total",
+            "counter",
+            "Console lines captured since daemon start",
+            |s| s.console_lines as i64,
+        ),
+        (
+            "sd_service_console_bytes_total",
+            "counter",
+            "Console bytes captured since daemon start",
+            |s| s.console_bytes as i64,
+        ),
+        (
+            "sd_service_dropped_lines_total",
+            "counter",
+            "Console lines dropped by the output rate limit",
+            |s| s.dropped_lines as i64,
+        ),
+    ];
+    // writing into a String can't fail
+    for (name, kind, help, value) in service_metrics.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for service in metrics.services.iter() {
+            let _ = writeln!(
+                out,
+                "{}{{service=\"{}\",id=\"{}\"}} {}",
+                name,
+                label(&service.name),
+                service.id,
+                value(service)
+            );
+        }
+    }
+    let _ = writeln!(out, "# HELP sd_http_requests_total Handled HTTP requests");
+    let _ = writeln!(out, "# TYPE sd_http_requests_total counter");
+    for (i, count) in requests.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "sd_http_requests_total{{code=\"{}xx\"}} {}",
+            i + 1,
+            count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP sd_logins_stored Stored logins, including expired ones not yet cleaned up"
+    );
+    let _ = writeln!(out, "# TYPE sd_logins_stored gauge");
+    let _ = writeln!(out, "sd_logins_stored {}", metrics.logins);
+    let _ = writeln!(out, "# HELP sd_db_size_bytes Database size on disk");
+    let _ = writeln!(out, "# TYPE sd_db_size_bytes gauge");
+    let _ = writeln!(out, "sd_db_size_bytes {}", metrics.db_size);
+    out
+}
+
+pub async fn metrics(
+    req: HttpRequest,
+    access: web::Data<Option<MetricsAccess>>,
+) -> Result<HttpResponse, Error> {
+    let access = match access.get_ref() {
+        Some(v) => v,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with("Bearer "))
+        .map(|v| &v["Bearer ".len()..]);
+    if !allowed(access, req.peer_addr().map(|v| v.ip()), bearer) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let metrics = match ServiceController::from_registry()
+        .send(GetMetrics {})
+        .await?
+    {
+        Ok(v) => v,
+        Err(e) => return Ok(e.error_response()),
+    };
+    Ok(HttpResponse::Ok()
+        .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+        .body(render(&metrics, requests())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allowed() {
+        let access = MetricsAccess {
+            token: Some("secret".to_owned()),
+            allow_ips: vec!["10.0.0.1".parse().unwrap()],
+            bind_ip: "127.0.0.1".to_owned(),
+            bind_port: None,
+        };
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(allowed(&access, "10.0.0.1".parse().ok(), None));
+        assert!(allowed(&access, Some(local), Some("secret")));
+        assert!(!allowed(&access, Some(local), Some("secreT")));
+        assert!(!allowed(&access, Some(local), None));
+        assert!(!allowed(&access, None, Some("secre")));
+        let no_token = MetricsAccess {
+            token: None,
+            ..access
+        };
+        assert!(!allowed(&no_token, Some(local), Some("")));
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = DaemonMetrics {
+            services: vec![ServiceMetrics {
+                id: 1,
+                name: "foo \"bar\"".to_owned(),
+                state: State::Crashed,
+                uptime: 0,
+                restarts: 2,
+                crashes: 3,
+                last_exit_code: -1,
+                console_lines: 10,
+                console_bytes: 200,
+                dropped_lines: 0,
+            }],
+            logins: 4,
+            db_size: 1024,
+        };
+        let out = render(&metrics, [0, 7, 0, 1, 0]);
+        assert!(out.contains("# TYPE sd_service_crashes_total counter\n"));
+        assert!(out.contains("sd_service_state{service=\"foo \\\"bar\\\"\",id=\"1\"} 3\n"));
+        assert!(out.contains("sd_service_up{service=\"foo \\\"bar\\\"\",id=\"1\"} 0\n"));
+        assert!(
+            out.contains("sd_service_last_exit_code{service=\"foo \\\"bar\\\"\",id=\"1\"} -1\n")
+        );
+        assert!(out.contains(
+            "sd_service_console_bytes_total{service=\"foo \\\"bar\\\"\",id=\"1\"} 200\n"
+        ));
+        assert!(out.contains("sd_http_requests_total{code=\"2xx\"} 7\n"));
+        assert!(out.contains("sd_http_requests_total{code=\"4xx\"} 1\n"));
+        assert!(out.contains("sd_logins_stored 4\n"));
+        assert!(out.contains("sd_db_size_bytes 1024\n"));
+    }
+
+    #[test]
+    fn test_count_request() {
+        let before = requests();
+        count_request(StatusCode::NOT_FOUND);
+        assert!(requests()[3] > before[3]);
+    }
+}
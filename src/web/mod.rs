@@ -1,20 +1,55 @@
 pub mod api;
 
+pub mod metrics;
 pub mod models;
 pub mod websocket;
 
-use crate::settings::Web;
+use crate::settings::{MetricsAccess, Web};
 use actix_files as fs;
 use actix_identity::*;
 use actix_web::cookie::SameSite;
-use actix_web::dev::Server;
+use actix_web::dev::{Server, Service};
 use actix_web::middleware::Logger;
 use actix_web::{guard, web, App, HttpResponse, HttpServer};
 
+/// Serve metrics on their own listener
+fn start_metrics(access: &MetricsAccess, port: u16) -> std::io::Result<Server> {
+    let bind = format!("{}:{}", access.bind_ip, port);
+    let access = Some(access.clone());
+    Ok(HttpServer::new(move || {
+        App::new()
+            .data(access.clone())
+            .service(web::resource("/metrics").route(web::get().to(metrics::metrics)))
+    })
+    .workers(1)
+    .bind(bind)?
+    .run())
+}
+
 pub fn start(config: &Web, max_age_secs: u32) -> std::io::Result<Server> {
     //TODO: add CORS
+    let metrics_access = config.metrics.clone();
+    let mut shared_metrics = metrics_access.is_some();
+    if let Some(access) = metrics_access.as_ref() {
+        if let Some(port) = access.bind_port {
+            start_metrics(access, port)?;
+            shared_metrics = false;
+        }
+    }
     Ok(HttpServer::new(move || {
         App::new()
+            .data(metrics_access.clone())
+            .wrap_fn(|req, srv| {
+                let fut = srv.call(req);
+                async {
+                    let res = fut.await;
+                    match res.as_ref() {
+                        Ok(v) => metrics::count_request(v.status()),
+                        Err(e) => metrics::count_request(e.as_response_error().status_code()),
+                    }
+                    res
+                }
+            })
             .wrap(Logger::default())
             .wrap(IdentityService::new(
                 CookieIdentityPolicy::new(&[0; 32])
@@ -52,16 +87,30 @@ pub fn start(config: &Web, max_age_secs: u32) -> std::io::Result<Server> {
                 )
                 .service(web::scope("/service/{service}")
                     .service(web::resource("/state").route(web::get().to(api::state)))
+                    .service(web::resource("/usage").route(web::get().to(api::usage)))
                     .service(web::resource("/output").route(web::get().to(api::output)))
+                    .service(web::resource("/output/search").route(web::post().to(api::output_search)))
                     .service(web::resource("/input").route(web::post().to(api::input)))
                     .service(web::resource("/stop").route(web::post().to(api::stop)))
                     .service(web::resource("/start").route(web::post().to(api::start)))
                     .service(web::resource("/kill").route(web::post().to(api::kill)))
+                    .service(web::resource("/restart").route(web::post().to(api::restart)))
+                    .service(web::resource("/pause").route(web::post().to(api::pause)))
+                    .service(web::resource("/resume").route(web::post().to(api::resume)))
+                    .service(web::resource("/signal")
+                        .route(web::get().to(api::signals))
+                        .route(web::post().to(api::signal)))
                     .service(web::resource("/log/latest/{amount}").route(web::get().to(api::log_latest)))
                     .service(web::resource("/log/console/{log_id}").route(web::get().to(api::log_console)))
+                    .service(web::resource("/log/console/{log_id}/search").route(web::post().to(api::log_console_search)))
                     .service(web::resource("/log/details/{log_id}").route(web::get().to(api::log_details)))
+                    .service(web::resource("/log/files").route(web::get().to(api::log_files)))
+                    .service(web::resource("/log/files/{file}").route(web::get().to(api::log_file)))
                     // Permissions of current user for service
                     .service(web::resource("/permissions").route(web::get().to(api::session_service_perm)))
+                    .service(web::resource("/notifications")
+                        .route(web::get().to(api::get_notifications))
+                        .route(web::post().to(api::set_notifications)))
                 )
                 .service(web::resource("/services").route(web::get().to(api::services)))
                 .default_service(web::resource("")
@@ -71,6 +120,11 @@ pub fn start(config: &Web, max_age_secs: u32) -> std::io::Result<Server> {
                             .to(||HttpResponse::MethodNotAllowed()),
                 ))
             )
+            .configure(|cfg| {
+                if shared_metrics {
+                    cfg.service(web::resource("/metrics").route(web::get().to(metrics::metrics)));
+                }
+            })
             .service(fs::Files::new("/", "./static").index_file("index.html"))
             .default_service(web::get().to(api::fallback))
     })
@@ -20,6 +20,51 @@ pub struct LogRequest {
     pub log_id: LogID,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LogFileRequest {
+    pub service: SID,
+    pub file: String,
+}
+
+/// How stdin input is interpreted
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdinMode {
+    /// Text with appended newline
+    Line,
+    /// Text without newline
+    Raw,
+    /// Text with escape sequences such as `\x03`
+    Escaped,
+    /// Close stdin, input is ignored
+    Eof,
+}
+
+fn default_stdin_mode() -> StdinMode {
+    StdinMode::Line
+}
+
+/// Stdin input, plain string for line mode or with explicit mode
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StdinRequest {
+    Line(String),
+    Input {
+        #[serde(default)]
+        input: String,
+        #[serde(default = "default_stdin_mode")]
+        mode: StdinMode,
+        /// Redact input in logs and console
+        #[serde(default)]
+        sensitive: bool,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignalRequest {
+    pub signal: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UserRequest {
     pub user: UID,
@@ -60,6 +105,12 @@ pub struct ServicePermWrap {
     pub perms: u32,
 }
 
+/// Mail notification subscription bits
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyEventsWrap {
+    pub events: u32,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserMin {
     pub name: String,